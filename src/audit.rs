@@ -0,0 +1,106 @@
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// File every outbound request is recorded in, set with `--audit-log`
+static AUDIT_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// A line of the audit log, describing a single outbound request
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    /// Seconds since the unix epoch when the request finished
+    timestamp: u64,
+    method: &'a str,
+    /// Requested url with the api key redacted
+    url: String,
+    /// Status of the response, missing when no response was received
+    status: Option<u16>,
+    /// Bytes of the response body that were read
+    bytes: u64,
+    duration_ms: u64,
+}
+
+/// Opens the audit log for appending, every request made afterwards is recorded in it
+pub(crate) fn open_audit_log(path: &Path) -> Result<(), String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|_| format!("Could not open audit log: {}", path.to_string_lossy()))?;
+    AUDIT_LOG
+        .set(Mutex::new(file))
+        .map_err(|_| "The audit log is already open".to_string())
+}
+
+/// Replaces the value of the `key` query parameter so the api key never ends up in the audit log
+pub(crate) fn redact_api_key(url: &str) -> String {
+    let Some((base_url, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+
+    let query = query
+        .split('&')
+        .map(|pair| {
+            if pair.starts_with("key=") {
+                "key=REDACTED"
+            } else {
+                pair
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("&");
+    format!("{}?{}", base_url, query)
+}
+
+/// Appends a request to the audit log, when one is open
+pub(crate) fn record_request(
+    method: &str,
+    url: &str,
+    status: Option<StatusCode>,
+    bytes: u64,
+    duration: Duration,
+) {
+    let Some(audit_log) = AUDIT_LOG.get() else {
+        return;
+    };
+
+    let entry = AuditEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default(),
+        method,
+        url: redact_api_key(url),
+        status: status.map(|status| status.as_u16()),
+        bytes,
+        duration_ms: duration.as_millis() as u64,
+    };
+    if let Ok(line) = serde_json::to_string(&entry) {
+        writeln!(audit_log.lock().unwrap(), "{}", line).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_api_key_only_replaces_the_key() {
+        assert_eq!(
+            redact_api_key(
+                "https://www.googleapis.com/webfonts/v1/webfonts?key=secret123&family=Open%20Sans"
+            ),
+            "https://www.googleapis.com/webfonts/v1/webfonts?key=REDACTED&family=Open%20Sans"
+        );
+        assert_eq!(
+            redact_api_key("https://fonts.gstatic.com/s/inter/v13/inter.ttf"),
+            "https://fonts.gstatic.com/s/inter/v13/inter.ttf"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name of the file the progress of a batch install is written to in the target directory
+pub(crate) const BATCH_STATE_NAME: &str = ".gfontapi-batch.json";
+
+/// Progress of a `--from-file` batch install, so an interrupted batch can be resumed
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct BatchState {
+    /// Font list the batch was started from
+    pub from_file: PathBuf,
+    /// Names of the fonts that finished installing, in the order they finished
+    pub completed: Vec<String>,
+}
+
+impl BatchState {
+    /// Loads the progress of a batch started from the same font list, an empty state is
+    /// returned when there is none or it belongs to another font list
+    pub(crate) fn load(output_dir: &Path, from_file: &Path) -> Result<BatchState, String> {
+        let state_path = output_dir.join(BATCH_STATE_NAME);
+        let empty_state = BatchState {
+            from_file: from_file.to_path_buf(),
+            completed: vec![],
+        };
+        if !state_path.exists() {
+            return Ok(empty_state);
+        }
+
+        let contents = std::fs::read_to_string(&state_path)
+            .map_err(|_| format!("Could not read batch progress: {:?}", state_path))?;
+        let state: BatchState = serde_json::from_str(&contents)
+            .map_err(|_| format!("Could not parse batch progress: {:?}", state_path))?;
+        if state.from_file != from_file {
+            return Ok(empty_state);
+        }
+
+        Ok(state)
+    }
+
+    /// Whether a font finished installing in an earlier run of the batch
+    pub(crate) fn is_completed(&self, fontname: &str) -> bool {
+        self.completed.iter().any(|completed| completed == fontname)
+    }
+
+    /// Records that a font finished installing and writes the progress to the target directory
+    pub(crate) fn complete(&mut self, output_dir: &Path, fontname: &str) -> Result<(), String> {
+        if !self.is_completed(fontname) {
+            self.completed.push(fontname.to_string());
+        }
+
+        let state_path = output_dir.join(BATCH_STATE_NAME);
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|_| "Could not serialize batch progress".to_string())?;
+        std::fs::write(&state_path, contents + "\n")
+            .map_err(|_| format!("Could not create file at path: {:?}", state_path))
+    }
+
+    /// Removes the progress file once the whole batch is installed
+    pub(crate) fn remove(output_dir: &Path) -> Result<(), String> {
+        let state_path = output_dir.join(BATCH_STATE_NAME);
+        if !state_path.exists() {
+            return Ok(());
+        }
+
+        std::fs::remove_file(&state_path)
+            .map_err(|_| format!("Could not delete file: {}", state_path.to_string_lossy()))
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    lockfile::{LockedFamily, Lockfile},
+    utils::sha256_file,
+};
+
+/// Name of the manifest at the root of a bundle
+const MANIFEST_NAME: &str = "gfbundle.json";
+
+/// Version of the bundle format written by `export_bundle`
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// Describes the font family packaged in a bundle, so it can be installed without network access
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleManifest {
+    format_version: u32,
+    /// Name of the font directory, every other file of the bundle is inside it
+    directory: String,
+    /// Lockfile entry of the font family, with the checksums of its files
+    #[serde(default)]
+    locked: Option<LockedFamily>,
+}
+
+/// Packages an installed font family into a bundle: a tar archive with a manifest and every file of
+/// the font directory, including the stylesheets so they are restored exactly. Returns the packaged file names
+pub(crate) fn export_bundle(
+    output_dir: &Path,
+    family_name: &str,
+    bundle_path: &Path,
+) -> Result<Vec<String>, String> {
+    let font_dir = output_dir.join(family_name);
+    if !font_dir.is_dir() {
+        return Err(format!(
+            "Could not find font directory: {}",
+            font_dir.to_string_lossy()
+        ));
+    }
+
+    let manifest = BundleManifest {
+        format_version: BUNDLE_FORMAT_VERSION,
+        directory: family_name.to_string(),
+        locked: Lockfile::load(output_dir)?.families.remove(family_name),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)
+        .map_err(|_| "Could not serialize the bundle manifest".to_string())?;
+
+    let bundle_file = File::create(bundle_path)
+        .map_err(|_| format!("Could not create file at path: {:?}", bundle_path))?;
+    let mut builder = tar::Builder::new(bundle_file);
+    let write_error = |_| format!("Could not write to file: {:?}", bundle_path);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, MANIFEST_NAME, manifest.as_slice())
+        .map_err(write_error)?;
+
+    let mut file_names: Vec<String> = std::fs::read_dir(&font_dir)
+        .map_err(|_| format!("Could not read directory: {}", font_dir.to_string_lossy()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    file_names.sort();
+    for file_name in &file_names {
+        builder
+            .append_path_with_name(
+                font_dir.join(file_name),
+                Path::new(family_name).join(file_name),
+            )
+            .map_err(write_error)?;
+    }
+    builder.finish().map_err(write_error)?;
+
+    Ok(file_names)
+}
+
+/// Unpacks a bundle into the target directory and records the font family in the lockfile.
+/// Fails without touching the font directory when a file does not match the checksum in the manifest. Returns the font directory
+/// and the unpacked file names
+pub(crate) fn import_bundle(
+    output_dir: &Path,
+    bundle_path: &Path,
+) -> Result<(PathBuf, Vec<String>), String> {
+    let read_error = |_| format!("Could not read bundle: {}", bundle_path.to_string_lossy());
+
+    // The manifest is read first, so nothing is unpacked from a bundle without one
+    let mut archive = File::open(bundle_path)
+        .map(tar::Archive::new)
+        .map_err(|_| format!("Could not open bundle: {}", bundle_path.to_string_lossy()))?;
+    let mut manifest = None;
+    for entry in archive.entries().map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        if entry.path().map_err(read_error)?.as_ref() == Path::new(MANIFEST_NAME) {
+            manifest = Some(
+                serde_json::from_reader::<_, BundleManifest>(entry).map_err(|_| {
+                    format!(
+                        "Could not parse the manifest of bundle: {}",
+                        bundle_path.to_string_lossy()
+                    )
+                })?,
+            );
+            break;
+        }
+    }
+    let manifest = manifest.ok_or_else(|| {
+        format!(
+            "{} is not a bundle, it has no {}",
+            bundle_path.to_string_lossy(),
+            MANIFEST_NAME
+        )
+    })?;
+    let mut components = Path::new(&manifest.directory).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(format!(
+            "{} has an unsafe font directory: {}",
+            bundle_path.to_string_lossy(),
+            manifest.directory
+        ));
+    }
+    if manifest.format_version > BUNDLE_FORMAT_VERSION {
+        return Err(format!(
+            "{} was exported by a newer version of gfontapi",
+            bundle_path.to_string_lossy()
+        ));
+    }
+
+    // The bundle is unpacked and checked next to the font directory, which is only touched once every
+    // file matches the manifest
+    let font_dir = output_dir.join(&manifest.directory);
+    let staging_dir = output_dir.join(format!(".{}.import", manifest.directory));
+    std::fs::create_dir_all(&staging_dir).map_err(|_| {
+        format!(
+            "Could not create directory: {}",
+            staging_dir.to_string_lossy()
+        )
+    })?;
+    let unpacked =
+        unpack_bundle(bundle_path, &manifest, &staging_dir, &font_dir).and_then(|file_names| {
+            std::fs::create_dir_all(&font_dir).map_err(|_| {
+                format!("Could not create directory: {}", font_dir.to_string_lossy())
+            })?;
+            for file_name in &file_names {
+                let file_path = font_dir.join(file_name);
+                std::fs::rename(
+                    staging_dir.join(&manifest.directory).join(file_name),
+                    &file_path,
+                )
+                .map_err(|_| format!("Could not create file at path: {:?}", file_path))?;
+            }
+            Ok(file_names)
+        });
+    std::fs::remove_dir_all(&staging_dir).ok();
+    let file_names = unpacked?;
+
+    if let Some(locked) = manifest.locked {
+        let mut lockfile = Lockfile::load(output_dir)?;
+        lockfile.families.insert(manifest.directory, locked);
+        lockfile.save(output_dir)?;
+    }
+
+    Ok((font_dir, file_names))
+}
+
+/// Unpacks the files of a bundle into the staging directory and checks them against the checksums
+/// in the manifest. Returns the unpacked file names
+fn unpack_bundle(
+    bundle_path: &Path,
+    manifest: &BundleManifest,
+    staging_dir: &Path,
+    font_dir: &Path,
+) -> Result<Vec<String>, String> {
+    let read_error = |_| format!("Could not read bundle: {}", bundle_path.to_string_lossy());
+    let mut archive = File::open(bundle_path)
+        .map(tar::Archive::new)
+        .map_err(|_| format!("Could not open bundle: {}", bundle_path.to_string_lossy()))?;
+    let mut file_names = vec![];
+    for entry in archive.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        let path = entry.path().map_err(read_error)?.into_owned();
+        let Ok(file_name) = path.strip_prefix(&manifest.directory) else {
+            continue;
+        };
+        let file_name = file_name.to_string_lossy().to_string();
+
+        // `unpack_in` refuses paths that would end up outside of the target directory
+        let unpacked = entry.unpack_in(staging_dir).map_err(|_| {
+            format!(
+                "Could not unpack {} to {}",
+                file_name,
+                font_dir.to_string_lossy()
+            )
+        })?;
+        if !unpacked {
+            return Err(format!(
+                "{} has an unsafe path: {}",
+                bundle_path.to_string_lossy(),
+                file_name
+            ));
+        }
+        file_names.push(file_name);
+    }
+
+    if let Some(locked) = &manifest.locked {
+        let unpacked_dir = staging_dir.join(&manifest.directory);
+        for (file_name, checksum) in &locked.files {
+            if sha256_file(&unpacked_dir.join(file_name))? != checksum.sha256 {
+                return Err(format!(
+                    "{} does not match the checksum in the bundle",
+                    font_dir.join(file_name).to_string_lossy()
+                ));
+            }
+        }
+    }
+
+    Ok(file_names)
+}
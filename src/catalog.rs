@@ -0,0 +1,256 @@
+use indicatif::HumanBytes;
+use owo_colors::OwoColorize;
+use regex::Regex;
+use reqwest::{Client, StatusCode};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::GfontError,
+    fonts::{Font, FontFamily},
+    utils::{fetch_text_with_retries, get_gfontapi_dir, ApiConfig},
+};
+
+/// How long a cached catalog is used before it is fetched again
+const CATALOG_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Fields of each font family requested for the catalog. Leaving out the `files` of every
+/// family makes the response a fraction of the size
+const CATALOG_FIELDS: &str = "items(family,category,variants,subsets,version,lastModified)";
+
+/// All the font families available from the google fonts API
+pub(crate) struct Catalog {
+    pub families: Vec<FontFamily>,
+    /// Time since the catalog was fetched from the API
+    pub age: Duration,
+}
+
+/// Path of the cached catalog, `~/.gfontapi/catalog.json`
+fn get_catalog_path() -> PathBuf {
+    get_gfontapi_dir().join("catalog.json")
+}
+
+/// How the cached catalog is used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CatalogCache {
+    /// Use the cached catalog while it is less than a day old
+    Use,
+    /// Fetch the catalog again and cache it
+    Refresh,
+    /// Fetch the catalog without reading or writing the cache
+    Bypass,
+}
+
+/// Loads the catalog from the cache, fetching it from the API when there is no cached catalog,
+/// the cached catalog is older than a day or the cache is not used
+pub(crate) async fn load_catalog(
+    client: &Client,
+    api: &ApiConfig,
+    cache: CatalogCache,
+    retries: u32,
+) -> Result<Catalog, Box<dyn std::error::Error>> {
+    let catalog_path = get_catalog_path();
+    let cached_age = std::fs::metadata(&catalog_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .filter(|_| cache == CatalogCache::Use);
+
+    if let Some(age) = cached_age.filter(|age| *age < CATALOG_MAX_AGE) {
+        let body = std::fs::read_to_string(&catalog_path)?;
+        if let Ok(font_data) = serde_json::from_str::<Font>(&body) {
+            return Ok(Catalog {
+                families: font_data.items,
+                age,
+            });
+        }
+    }
+
+    let api_url = format!(
+        "{base_url}?key={key}&fields={fields}",
+        base_url = api.base_url,
+        key = api.key,
+        fields = CATALOG_FIELDS
+    );
+    let (status, body) = fetch_text_with_retries(client.get(&api_url), &api_url, retries)
+        .await
+        .map_err(|err| format!("Failed to fetch the font catalog: {}", err.without_url()))?;
+    if status != StatusCode::OK {
+        return Err(format!("Failed to fetch the font catalog: {}", status).into());
+    }
+
+    let font_data: Font =
+        serde_json::from_str(&body).map_err(|_| "Could not parse the font catalog".to_string())?;
+
+    if cache == CatalogCache::Bypass {
+        return Ok(Catalog {
+            families: font_data.items,
+            age: Duration::ZERO,
+        });
+    }
+
+    std::fs::create_dir_all(get_gfontapi_dir())?;
+    if let Err(err) = std::fs::write(&catalog_path, &body) {
+        eprintln!(
+            "{}: Could not cache the font catalog at {}\n  {}: {}",
+            "warning".yellow(),
+            catalog_path.to_string_lossy(),
+            "Caused by".yellow(),
+            err
+        );
+    }
+
+    Ok(Catalog {
+        families: font_data.items,
+        age: Duration::ZERO,
+    })
+}
+
+/// Reads the catalog from a json file of `--catalog-file` in the format the API responds with,
+/// e.g. a saved response or the cached `~/.gfontapi/catalog.json`, without any network access
+pub(crate) fn read_catalog_file(catalog_file: &Path) -> Result<Catalog, String> {
+    let body = std::fs::read_to_string(catalog_file).map_err(|_| {
+        format!(
+            "Could not read catalog file: {}",
+            catalog_file.to_string_lossy()
+        )
+    })?;
+    let font_data: Font = serde_json::from_str(&body).map_err(|_| {
+        format!(
+            "Could not parse catalog file: {}",
+            catalog_file.to_string_lossy()
+        )
+    })?;
+
+    Ok(Catalog {
+        families: font_data.items,
+        age: std::fs::metadata(catalog_file)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default(),
+    })
+}
+
+/// Prints which catalog file the catalog commands read
+pub(crate) fn print_catalog_file(catalog_file: &Path) {
+    println!(
+        "{} {}",
+        "Using font catalog from".dimmed(),
+        catalog_file.to_string_lossy().cyan()
+    );
+}
+
+/// Formats the age of the catalog in the largest whole unit, e.g. `3 hours ago`
+pub(crate) fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    let (amount, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    format!(
+        "{} {}{} ago",
+        amount,
+        unit,
+        if amount == 1 { "" } else { "s" }
+    )
+}
+
+/// Prints how old the catalog is so stale results can be refreshed
+pub(crate) fn print_catalog_age(catalog: &Catalog) {
+    println!(
+        "{} {} {}",
+        "Using font catalog fetched".dimmed(),
+        format_age(catalog.age).cyan(),
+        "(pass --refresh-catalog to fetch it again)".dimmed()
+    );
+}
+
+/// Prints how many font families the freshly cached catalog has and how large it is
+pub(crate) fn print_cached_catalog(catalog: &Catalog) {
+    let catalog_path = get_catalog_path();
+    let size = std::fs::metadata(&catalog_path)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+    println!(
+        "{} {} {} {}",
+        "Cached".dimmed(),
+        format!("{} font families", catalog.families.len()).cyan(),
+        format!("({})", HumanBytes(size)).dimmed(),
+        format!("to {}", catalog_path.to_string_lossy()).dimmed()
+    );
+}
+
+/// Prints a font family with its category
+pub(crate) fn print_font_family(font_family: &FontFamily) {
+    println!(
+        " {} {}",
+        &font_family.family,
+        format!("({})", font_family.category).dimmed()
+    );
+}
+
+/// Lists the font families in the catalog, only the ones whose name matches `regex`
+/// and that are in `category` when they are set
+pub(crate) fn list_families(
+    catalog: &Catalog,
+    regex: Option<&Regex>,
+    category: Option<&str>,
+) -> Result<(), GfontError> {
+    let matches: Vec<&FontFamily> = catalog
+        .families
+        .iter()
+        .filter(|font_family| regex.is_none_or(|regex| regex.is_match(&font_family.family)))
+        .filter(|font_family| {
+            category.is_none_or(|category| font_family.category.eq_ignore_ascii_case(category))
+        })
+        .collect();
+
+    if matches.is_empty() && (regex.is_some() || category.is_some()) {
+        return Err(GfontError::NoMatch { query: None });
+    }
+
+    for font_family in matches {
+        print_font_family(font_family);
+    }
+    Ok(())
+}
+
+/// Lists the font families whose name contains the query, ignoring case
+pub(crate) fn search_families(catalog: &Catalog, query: &str) -> Result<(), GfontError> {
+    let query = query.to_lowercase();
+    let matches: Vec<&FontFamily> = catalog
+        .families
+        .iter()
+        .filter(|font_family| font_family.family.to_lowercase().contains(&query))
+        .collect();
+
+    if matches.is_empty() {
+        return Err(GfontError::NoMatch { query: Some(query) });
+    }
+
+    for font_family in matches {
+        print_font_family(font_family);
+    }
+    Ok(())
+}
+
+/// Prints a random font family from the catalog
+pub(crate) fn random_family(catalog: &Catalog) -> Result<(), GfontError> {
+    if catalog.families.is_empty() {
+        return Err(GfontError::EmptyCatalog);
+    }
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos() as usize)
+        .unwrap_or_default();
+
+    print_font_family(&catalog.families[seed % catalog.families.len()]);
+    Ok(())
+}
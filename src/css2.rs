@@ -0,0 +1,273 @@
+use reqwest::{header::USER_AGENT, Client, StatusCode};
+
+use crate::{
+    fonts::{FileNaming, FontStyles},
+    utils::fetch_text_with_retries,
+};
+
+/// Url of the google fonts css2 API, which serves woff2 files split into unicode subsets
+const CSS2_URL: &str = "https://fonts.googleapis.com/css2";
+
+/// The css2 API picks the font format from the user agent, a current browser gets woff2 files
+const CSS2_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36";
+
+/// Subset name of the faces served for `--css2-text`, which contain only the glyphs of the text
+pub(crate) const TEXT_SUBSET: &str = "text";
+
+/// An `@font-face` rule served by the css2 API for one unicode subset of a font style
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Css2Face {
+    /// Name of the subset, e.g. `latin-ext`. Large fonts are split into numbered slices instead, e.g. `0`
+    pub subset: String,
+    pub font_style: FontStyles,
+    pub url: String,
+    /// Missing for the faces served for `--css2-text`, they cover the glyphs of the text only
+    pub unicode_range: Option<String>,
+}
+
+impl Css2Face {
+    pub(crate) fn file_name(&self, font_family_name: &str, naming: FileNaming) -> String {
+        subset_file_name(&self.font_style, font_family_name, naming, &self.subset)
+    }
+}
+
+/// File name of the woff2 file for a subset of a font style, e.g. `inter-bold-latin-ext.woff2`,
+/// or `bold-latin-ext.woff2` without a family name
+pub(crate) fn subset_file_name(
+    font_style: &FontStyles,
+    font_family_name: &str,
+    naming: FileNaming,
+    subset: &str,
+) -> String {
+    let file_stem = format!("{}-{}", font_style.file_suffix(naming), subset);
+    if font_family_name.is_empty() {
+        return format!("{}.woff2", file_stem);
+    }
+    format!("{}-{}.woff2", font_family_name, file_stem)
+}
+
+/// Resolves the subsets passed to `--subsets` against the subsets available for a font family.
+/// `all` selects every available subset
+pub(crate) fn resolve_subsets(
+    requested: &[String],
+    available: &[String],
+) -> Result<Vec<String>, String> {
+    if requested.iter().any(|subset| subset == "all") {
+        return Ok(available.to_vec());
+    }
+
+    for subset in requested {
+        if !available.contains(subset) {
+            return Err(format!(
+                "The `{}` subset is not available, available subsets: {}",
+                subset,
+                available.join(", ")
+            ));
+        }
+    }
+
+    Ok(requested.to_vec())
+}
+
+/// Builds the css2 API url requesting the font styles of a font family,
+/// e.g. `?family=Inter:ital,wght@0,400;1,700`. With a text, only the glyphs of the text are served
+fn css2_url(font_family: &str, font_styles: &[FontStyles], text: Option<&str>) -> String {
+    let mut axes: Vec<(u8, u16)> = font_styles
+        .iter()
+        .map(|font_style| {
+            let (font_style_name, font_weight) = font_style.get_style_and_weight();
+            (u8::from(font_style_name == "italic"), font_weight)
+        })
+        .collect();
+    axes.sort();
+
+    let mut url = format!(
+        "{}?family={}:ital,wght@{}",
+        CSS2_URL,
+        font_family.replace(' ', "+"),
+        axes.iter()
+            .map(|(italic, font_weight)| format!("{},{}", italic, font_weight))
+            .collect::<Vec<String>>()
+            .join(";")
+    );
+    if let Some(text) = text {
+        url.push_str("&text=");
+        url.push_str(&encode_query_value(text));
+    }
+    url
+}
+
+/// Percent-encodes a query parameter value, every byte except the unreserved characters is escaped
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Fetches the `@font-face` rules of every subset of the font styles from the css2 API.
+/// With a text, a single face per font style containing only the glyphs of the text is fetched instead
+pub(crate) async fn fetch_css2_faces(
+    client: &Client,
+    font_family: &str,
+    font_styles: &[FontStyles],
+    text: Option<&str>,
+    retries: u32,
+) -> Result<Vec<Css2Face>, String> {
+    let url = css2_url(font_family, font_styles, text);
+    let (status, css) = fetch_text_with_retries(
+        client.get(&url).header(USER_AGENT, CSS2_USER_AGENT),
+        &url,
+        retries,
+    )
+    .await
+    .map_err(|err| format!("Failed to fetch `{}`: {}", url, err.without_url()))?;
+    if status != StatusCode::OK {
+        return Err(format!("Failed to fetch `{}`: {}", url, status));
+    }
+
+    parse_css2_faces(&css)
+}
+
+/// Parses the `@font-face` rules returned by the css2 API. Every rule is preceded by a comment naming its subset:
+///
+/// ```css
+/// /* latin-ext */
+/// @font-face {
+///   font-family: 'Inter';
+///   font-style: normal;
+///   font-weight: 400;
+///   src: url(https://fonts.gstatic.com/s/inter/v13/....woff2) format('woff2');
+///   unicode-range: U+0100-02AF, U+0304, ...;
+/// }
+/// ```
+///
+/// The rules served for a text have no subset comment and no `unicode-range`, they are named [`TEXT_SUBSET`]
+pub(crate) fn parse_css2_faces(css: &str) -> Result<Vec<Css2Face>, String> {
+    let mut faces = vec![];
+    let rules: Vec<&str> = css.split("@font-face").collect();
+    for (index, rule) in rules.iter().enumerate().skip(1) {
+        let Some(block) = rule
+            .split_once('{')
+            .and_then(|(_, block)| block.split_once('}'))
+            .map(|(block, _)| block)
+        else {
+            continue;
+        };
+        // The subset comment is between the end of the previous rule and this one
+        let preceding = rules[index - 1];
+        let preceding = preceding
+            .rsplit_once('}')
+            .map_or(preceding, |(_, preceding)| preceding);
+        let subset = preceding
+            .split_once("/*")
+            .and_then(|(_, comment)| comment.split_once("*/"))
+            .map_or(TEXT_SUBSET, |(subset, _)| subset)
+            .trim()
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_string();
+
+        let mut font_style_name = "normal".to_string();
+        let mut font_weight = 400;
+        let mut url = None;
+        let mut unicode_range = None;
+        for declaration in block.split(';') {
+            let Some((property, value)) = declaration.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match property.trim() {
+                "font-style" => font_style_name = value.to_string(),
+                "font-weight" => {
+                    font_weight = value.parse::<u16>().map_err(|_| {
+                        format!("Unsupported font-weight `{}` in css2 response", value)
+                    })?
+                }
+                "src" => {
+                    url = value
+                        .strip_prefix("url(")
+                        .and_then(|src| src.split_once(')'))
+                        .map(|(url, _)| url.to_string())
+                }
+                "unicode-range" => unicode_range = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        let Some(url) = url else {
+            continue;
+        };
+        let font_style = FontStyles::from_weight(font_weight, font_style_name == "italic")
+            .ok_or_else(|| format!("Unsupported font-weight `{}` in css2 response", font_weight))?;
+
+        faces.push(Css2Face {
+            subset,
+            font_style,
+            url,
+            unicode_range,
+        });
+    }
+
+    if faces.is_empty() {
+        return Err("The css2 response has no @font-face rules".to_string());
+    }
+
+    Ok(faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_css2_faces_reads_each_subset() {
+        let faces = parse_css2_faces(include_str!("../tests/fixtures/css2_inter.css")).unwrap();
+
+        assert_eq!(
+            faces
+                .iter()
+                .map(|face| (face.subset.as_str(), face.font_style.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("cyrillic", FontStyles::BoldItalic),
+                ("latin-ext", FontStyles::BoldItalic),
+                ("latin", FontStyles::Regular),
+                ("1", FontStyles::Regular),
+            ]
+        );
+        assert_eq!(
+            faces[3].url,
+            "https://fonts.gstatic.com/s/inter/v18/slice1.woff2"
+        );
+        assert_eq!(faces[3].unicode_range.as_deref(), Some("U+4E00-4E3F"));
+    }
+
+    #[test]
+    fn parse_css2_faces_reads_text_faces_without_subset_comments() {
+        let css = "@font-face {\n  font-family: 'Inter';\n  font-style: normal;\n  font-weight: 700;\n  src: url(https://fonts.gstatic.com/l/font?kit=abc&skey=def&v=v18) format('woff2');\n}\n";
+        let faces = parse_css2_faces(css).unwrap();
+
+        assert_eq!(faces.len(), 1);
+        assert_eq!(faces[0].subset, TEXT_SUBSET);
+        assert_eq!(faces[0].font_style, FontStyles::Bold);
+        assert_eq!(faces[0].unicode_range, None);
+        assert!(css2_url("Inter", &[FontStyles::Bold], Some("Company Name"))
+            .ends_with("&text=Company%20Name"));
+    }
+
+    #[test]
+    fn resolve_subsets_validates_against_available_subsets() {
+        let available = vec!["latin".to_string(), "latin-ext".to_string()];
+
+        assert_eq!(
+            resolve_subsets(&["all".to_string()], &available).unwrap(),
+            available
+        );
+        assert!(resolve_subsets(&["greek".to_string()], &available).is_err());
+    }
+}
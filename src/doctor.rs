@@ -0,0 +1,165 @@
+use owo_colors::OwoColorize;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::{confirm, get_gfontapi_dir, get_woff2_compress, Interaction};
+
+/// Aliases file written by `doctor --fix` when there is none
+const STARTER_ALIASES: &str =
+    "# Aliases for font names, one `alias -> Font Name` pair per line, e.g.\n# sans -> Open Sans\n";
+
+/// Finds an executable in the directories of `PATH`
+fn find_on_path(binary: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
+}
+
+fn print_ok(message: &str) {
+    println!(" {} {}", "=".green(), message);
+}
+
+fn print_problem(message: &str) {
+    println!(" {} {}", "!".red(), message);
+}
+
+fn print_fixed(message: &str) {
+    println!(" {} {}", "+".green(), message);
+}
+
+/// Links a `woff2_compress` found elsewhere into `~/.gfontapi/bin`, where gfontapi looks for it
+fn link_woff2_compress(source: &Path, target: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(source, target);
+    #[cfg(not(unix))]
+    return std::fs::copy(source, target).map(|_| ());
+}
+
+/// Checks that everything gfontapi needs is set up and prints the result of each check.
+/// With `fix`, offers to fix each problem after confirming it. Returns whether every check passed
+pub(crate) fn run_doctor(
+    api_key_set: bool,
+    fix: bool,
+    interaction: Interaction,
+) -> Result<bool, String> {
+    let mut problem_count = 0;
+    let bin_dir = get_gfontapi_dir().join("bin");
+
+    if bin_dir.is_dir() {
+        print_ok(&format!("{} exists", bin_dir.to_string_lossy()));
+    } else if fix
+        && confirm(
+            &format!("Create {}?", bin_dir.to_string_lossy()),
+            interaction,
+        )?
+    {
+        std::fs::create_dir_all(&bin_dir)
+            .map_err(|_| format!("Could not create directory: {}", bin_dir.to_string_lossy()))?;
+        print_fixed(&format!("created {}", bin_dir.to_string_lossy()));
+    } else {
+        print_problem(&format!("{} does not exist", bin_dir.to_string_lossy()));
+        problem_count += 1;
+    }
+
+    match get_woff2_compress() {
+        Ok(woff2_compress) => print_ok(&format!(
+            "woff2_compress found at {}",
+            woff2_compress.to_string_lossy()
+        )),
+        Err(_) => match find_on_path("woff2_compress") {
+            Some(woff2_compress)
+                if fix
+                    && bin_dir.is_dir()
+                    && confirm(
+                        &format!(
+                            "Link {} into {}?",
+                            woff2_compress.to_string_lossy(),
+                            bin_dir.to_string_lossy()
+                        ),
+                        interaction,
+                    )? =>
+            {
+                let target = bin_dir.join("woff2_compress");
+                link_woff2_compress(&woff2_compress, &target).map_err(|_| {
+                    format!(
+                        "Could not link woff2_compress to {}",
+                        target.to_string_lossy()
+                    )
+                })?;
+                print_fixed(&format!(
+                    "linked {} to {}",
+                    woff2_compress.to_string_lossy(),
+                    target.to_string_lossy()
+                ));
+            }
+            Some(woff2_compress) => {
+                print_problem(&format!(
+                    "woff2_compress is at {} but gfontapi only looks in /usr/local/bin and {}, run doctor --fix to link it",
+                    woff2_compress.to_string_lossy(),
+                    bin_dir.to_string_lossy()
+                ));
+                problem_count += 1;
+            }
+            None => {
+                // google/woff2 doesn't publish binaries, so there is nothing to download
+                print_problem(&format!(
+                    "woff2_compress is not installed, install woff2 with your package manager (e.g. {} or {}) or build it from {}",
+                    "apt install woff2".cyan(),
+                    "brew install woff2".cyan(),
+                    "https://github.com/google/woff2".cyan()
+                ));
+                problem_count += 1;
+            }
+        },
+    }
+
+    if api_key_set {
+        print_ok("api key is set");
+    } else {
+        print_problem("no api key, export GFONT_API_KEY or pass --api-key");
+        problem_count += 1;
+    }
+
+    let aliases_path = get_gfontapi_dir().join("aliases");
+    if aliases_path.is_file() {
+        print_ok(&format!(
+            "aliases file at {}",
+            aliases_path.to_string_lossy()
+        ));
+    } else if fix
+        && confirm(
+            &format!(
+                "Write a starter aliases file to {}?",
+                aliases_path.to_string_lossy()
+            ),
+            interaction,
+        )?
+    {
+        std::fs::create_dir_all(get_gfontapi_dir()).map_err(|_| {
+            format!(
+                "Could not create directory: {}",
+                get_gfontapi_dir().to_string_lossy()
+            )
+        })?;
+        std::fs::write(&aliases_path, STARTER_ALIASES)
+            .map_err(|_| format!("Could not create file at path: {:?}", aliases_path))?;
+        print_fixed(&format!(
+            "wrote a starter aliases file to {}",
+            aliases_path.to_string_lossy()
+        ));
+    } else {
+        println!(
+            " {} {}",
+            "-".dimmed(),
+            format!(
+                "no aliases file at {} (optional)",
+                aliases_path.to_string_lossy()
+            )
+            .dimmed()
+        );
+    }
+
+    Ok(problem_count == 0)
+}
@@ -1,18 +1,172 @@
-// #[derive(Debug)]
-// enum ApiError {
-//     RequestFailed(reqwest::Error),
-//     BadStatus(StatusCode),
-//     ParseError(String),
-// }
-
-// impl std::fmt::Display for ApiError {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         match self {
-//             ApiError::RequestFailed(err) => write!(f, "Request failed: {}", err),
-//             ApiError::BadStatus(status) => write!(f, "Bad status code: {}", status),
-//             ApiError::ParseError(msg) => write!(f, "Parse error: {}", msg),
-//         }
-//     }
-// }
-
-// impl std::error::Error for ApiError {}
+use std::fmt;
+
+use owo_colors::OwoColorize;
+
+use crate::utils::print_json_error;
+
+/// Errors reported to the user. Each kind has a stable code and context, which `--json-errors`
+/// prints as a single line of json instead of colored text
+#[derive(Debug)]
+pub enum GfontError {
+    /// No api key was passed or set in the environment
+    MissingApiKey,
+    /// A font family could not be installed
+    InstallFailed { family: String, message: String },
+    /// The upstream version of a font family could not be compared with the lockfile by `--check`
+    CheckFailed { family: String, message: String },
+    /// A font file of a font family could not be downloaded
+    DownloadFailed {
+        family: String,
+        style: String,
+        message: String,
+    },
+    /// A downloaded font file could not be converted
+    ConversionFailed {
+        family: String,
+        style: String,
+        message: String,
+    },
+    /// A downloaded font file does not match the checksum recorded in the lockfile
+    ChecksumMismatch {
+        family: String,
+        style: String,
+        message: String,
+    },
+    /// A file generated for the installed fonts, e.g. a stylesheet, could not be written
+    WriteFailed { file: String, message: String },
+    /// Font files left by previous installs could not be removed by `--prune`
+    PruneFailed { family: String, message: String },
+    /// No font family in the catalog matches a search, or the filters of `list` without a query
+    NoMatch { query: Option<String> },
+    /// The catalog has no font families to pick from
+    EmptyCatalog,
+    /// No font families are installed in the target directory
+    NothingInstalled { dir: String },
+    /// Any other error that stops a command
+    Failed { command: String, message: String },
+}
+
+impl GfontError {
+    /// Stable code of the error, for scripts reading `--json-errors`
+    pub fn code(&self) -> &'static str {
+        match self {
+            GfontError::MissingApiKey => "missing_api_key",
+            GfontError::InstallFailed { .. } => "install_failed",
+            GfontError::CheckFailed { .. } => "check_failed",
+            GfontError::DownloadFailed { .. } => "download_failed",
+            GfontError::ConversionFailed { .. } => "conversion_failed",
+            GfontError::ChecksumMismatch { .. } => "checksum_mismatch",
+            GfontError::WriteFailed { .. } => "write_failed",
+            GfontError::PruneFailed { .. } => "prune_failed",
+            GfontError::NoMatch { .. } => "no_match",
+            GfontError::EmptyCatalog => "empty_catalog",
+            GfontError::NothingInstalled { .. } => "nothing_installed",
+            GfontError::Failed { .. } => "failed",
+        }
+    }
+
+    /// What the error is about, e.g. the font family that failed to install
+    pub fn context(&self) -> Vec<(&'static str, &str)> {
+        match self {
+            GfontError::MissingApiKey | GfontError::EmptyCatalog => vec![],
+            GfontError::InstallFailed { family, .. }
+            | GfontError::CheckFailed { family, .. }
+            | GfontError::PruneFailed { family, .. } => vec![("family", family)],
+            GfontError::DownloadFailed { family, style, .. }
+            | GfontError::ConversionFailed { family, style, .. }
+            | GfontError::ChecksumMismatch { family, style, .. } => {
+                vec![("family", family), ("style", style)]
+            }
+            GfontError::WriteFailed { file, .. } => vec![("file", file)],
+            GfontError::NoMatch { query } => query
+                .iter()
+                .map(|query| ("query", query.as_str()))
+                .collect(),
+            GfontError::NothingInstalled { dir } => vec![("dir", dir)],
+            GfontError::Failed { command, .. } => vec![("command", command)],
+        }
+    }
+
+    /// What went wrong, without its cause
+    fn summary(&self) -> String {
+        match self {
+            GfontError::MissingApiKey => "Using gfontapi requires an API key".to_string(),
+            GfontError::InstallFailed { family, .. } => format!("Failed to install `{}`", family),
+            GfontError::CheckFailed { family, .. } => format!("Failed to check `{}`", family),
+            GfontError::DownloadFailed { family, style, .. } => {
+                format!("Failed to download {}=={}", family, style)
+            }
+            GfontError::ConversionFailed { family, style, .. } => {
+                format!("Failed to convert {}=={}", family, style)
+            }
+            GfontError::ChecksumMismatch { family, style, .. } => {
+                format!("{}=={} does not match the lockfile", family, style)
+            }
+            GfontError::WriteFailed { file, .. } => format!("Failed to write {}", file),
+            GfontError::PruneFailed { family, .. } => {
+                format!("Failed to prune the font files of {}", family)
+            }
+            GfontError::NoMatch { query: Some(query) } => {
+                format!("No font families match `{}`", query)
+            }
+            GfontError::NoMatch { query: None } => "No font families match the filters".to_string(),
+            GfontError::EmptyCatalog => "The font catalog is empty".to_string(),
+            GfontError::NothingInstalled { dir } => {
+                format!("No font families are installed in {}", dir)
+            }
+            GfontError::Failed { message, .. } => message.clone(),
+        }
+    }
+
+    /// Underlying error of the kinds that wrap one
+    fn cause(&self) -> Option<&str> {
+        match self {
+            GfontError::InstallFailed { message, .. }
+            | GfontError::CheckFailed { message, .. }
+            | GfontError::DownloadFailed { message, .. }
+            | GfontError::ConversionFailed { message, .. }
+            | GfontError::ChecksumMismatch { message, .. }
+            | GfontError::WriteFailed { message, .. }
+            | GfontError::PruneFailed { message, .. } => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Prints the error to stderr, as a single line of json with `--json-errors`
+    pub fn report(&self, json_errors: bool) {
+        if json_errors {
+            print_json_error(self.code(), &self.to_string(), &self.context());
+            return;
+        }
+        match (self, self.cause()) {
+            (GfontError::MissingApiKey, _) => eprintln!(
+                "{}: {}.\
+                \n  {}\n    - export GFONT_API_KEY={}\n    - gfontapi --api-key={}",
+                "error".red(),
+                self.summary(),
+                "Pass it to the program in one of the following ways".dimmed(),
+                "<YOUR_API_KEY>".cyan(),
+                "<YOUR_API_KEY>".cyan()
+            ),
+            (_, Some(cause)) => eprintln!(
+                "{}: {}\n  {}: {}",
+                "error".red(),
+                self.summary(),
+                "Caused by".red(),
+                cause
+            ),
+            (_, None) => eprintln!("{}: {}", "error".red(), self.summary()),
+        }
+    }
+}
+
+impl fmt::Display for GfontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cause() {
+            Some(cause) => write!(f, "{}: {}", self.summary(), cause),
+            None => write!(f, "{}", self.summary()),
+        }
+    }
+}
+
+impl std::error::Error for GfontError {}
@@ -1,12 +1,269 @@
+use clap::ValueEnum;
 use owo_colors::OwoColorize;
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, process};
-use strum::Display;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    time::Instant,
+};
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 
-use crate::BASE_URL;
+use crate::{
+    audit::record_request,
+    sfnt::{NamedInstance, Sfnt},
+    utils::{fetch_text_with_retries, ApiConfig},
+};
 
-#[derive(Display, Clone, Debug)]
+/// Naming schemes for the font files written to the font directory
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum FileNaming {
+    /// Named weights, e.g. `inter-bold-italic.woff2`
+    #[default]
+    Named,
+    /// Numeric weights with an explicit italic suffix, e.g. `inter-700-italic.woff2`
+    Numeric,
+}
+
+/// Whether the font files written to the font directory start with the name of the font family
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum FilenameStyle {
+    /// The font family and style, e.g. `inter-bold.woff2`
+    #[default]
+    WithFamily,
+    /// Only the style, e.g. `bold.woff2`, since the font directory already names the family
+    StyleOnly,
+}
+
+impl FilenameStyle {
+    /// Prefix of the font file names of a font family, empty when they only name the style
+    pub(crate) fn prefix<'a>(&self, family_name: &'a str) -> &'a str {
+        match self {
+            FilenameStyle::WithFamily => family_name,
+            FilenameStyle::StyleOnly => "",
+        }
+    }
+}
+
+/// How the font files are laid out in the font directory
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Layout {
+    /// Every font file directly in the font directory, e.g. `inter/inter-regular.woff2`
+    #[default]
+    Flat,
+    /// A directory for each weight, e.g. `inter/400/inter-regular.woff2`
+    DirPerWeight,
+}
+
+impl Layout {
+    /// Path of a font file relative to the font directory, always separated by `/` so it can be used in urls
+    pub(crate) fn file_path(&self, font_style: &FontStyles, file_name: &str) -> String {
+        match self {
+            Layout::Flat => file_name.to_string(),
+            Layout::DirPerWeight => {
+                format!("{}/{}", font_style.get_style_and_weight().1, file_name)
+            }
+        }
+    }
+}
+
+/// Font file formats kept in the font directory
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum FontFormat {
+    /// Only the converted woff2 files, for the web
+    #[default]
+    Woff2,
+    /// Only the ttf files served by google fonts, skipping the conversion
+    Ttf,
+    /// Both the woff2 and ttf files, e.g. for the web and desktop
+    Both,
+    /// Only the otf files of font families distributed as OpenType, skipping the conversion
+    Otf,
+}
+
+/// How weights that were asked for but are not available in a font family are handled
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum WeightFallback {
+    /// Drop the weight with a warning suggesting the nearest available weight
+    #[default]
+    Strict,
+    /// Use the file of the nearest available weight for the missing weight
+    Nearest,
+}
+
+/// Finds the available weight closest to `font_weight`. Ties go to the lighter weight up to 500
+/// and to the heavier weight above it, like the css font matching algorithm
+pub(crate) fn nearest_weight(available: &[u16], font_weight: u16) -> Option<u16> {
+    available.iter().copied().min_by_key(|&candidate| {
+        let preferred_side = (font_weight > 500) == (candidate > font_weight);
+        (candidate.abs_diff(font_weight), !preferred_side)
+    })
+}
+
+/// Common sets of weights for `--weights-preset`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WeightsPreset {
+    /// Body text with medium and bold emphasis, 400,500,700
+    Text,
+    /// Headings, 400,700,900
+    Display,
+    /// Regular and bold, 400,700
+    Minimal,
+}
+
+impl WeightsPreset {
+    pub(crate) fn weights(&self) -> &'static [u16] {
+        match self {
+            WeightsPreset::Text => &[400, 500, 700],
+            WeightsPreset::Display => &[400, 700, 900],
+            WeightsPreset::Minimal => &[400, 700],
+        }
+    }
+}
+
+/// Decodes the `+` and percent encoded bytes of a url path segment, e.g. `Open+Sans` or `Open%20Sans`
+fn decode_url_segment(segment: &str) -> String {
+    let mut bytes = vec![];
+    let mut remaining = segment.as_bytes();
+    while let Some((&byte, rest)) = remaining.split_first() {
+        remaining = rest;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => match rest
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(decoded) => {
+                    bytes.push(decoded);
+                    remaining = &rest[2..];
+                }
+                None => bytes.push(byte),
+            },
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Parses the font family name out of a google fonts specimen url of `--family-from-url`,
+/// e.g. `Open Sans` from `https://fonts.google.com/specimen/Open+Sans?query=open`
+pub(crate) fn family_from_specimen_url(url: &str) -> Result<String, String> {
+    let invalid_url = || {
+        format!(
+            "`{}` is not a google fonts specimen url, e.g. https://fonts.google.com/specimen/Inter",
+            url
+        )
+    };
+
+    let path = url
+        .trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .strip_prefix("fonts.google.com/specimen/")
+        .ok_or_else(invalid_url)?;
+    let segment = path.split(['/', '?', '#']).next().unwrap_or_default();
+    let family = decode_url_segment(segment);
+    let family = family.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if family.is_empty() {
+        return Err(invalid_url());
+    }
+
+    Ok(family)
+}
+
+/// Parses the weights passed to `--weights`, skipping empty entries left by stray commas and dropping duplicates.
+/// Weights must be 100 to 900 in steps of 100, except with `--weight-fallback nearest` where any css weight
+/// from 1 to 1000 is mapped to the nearest available weight. Every invalid entry is listed in the error
+pub(crate) fn parse_weights(
+    weights: &[String],
+    weight_fallback: WeightFallback,
+) -> Result<Vec<u16>, String> {
+    let mut parsed_weights = vec![];
+    let mut invalid_weights = vec![];
+    for weight in weights.iter().map(|weight| weight.trim()) {
+        if weight.is_empty() {
+            continue;
+        }
+
+        match weight.parse::<u16>() {
+            Ok(font_weight)
+                if match weight_fallback {
+                    WeightFallback::Strict => {
+                        (100..=900).contains(&font_weight) && font_weight % 100 == 0
+                    }
+                    WeightFallback::Nearest => (1..=1000).contains(&font_weight),
+                } =>
+            {
+                if !parsed_weights.contains(&font_weight) {
+                    parsed_weights.push(font_weight);
+                }
+            }
+            _ => invalid_weights.push(format!("`{}`", weight)),
+        }
+    }
+
+    if !invalid_weights.is_empty() {
+        return Err(match weight_fallback {
+            WeightFallback::Strict => format!(
+                "Invalid weights {}, weights must be 100 to 900 in steps of 100 (pass --weight-fallback nearest to use the nearest available weight for others)",
+                invalid_weights.join(", ")
+            ),
+            WeightFallback::Nearest => format!(
+                "Invalid weights {}, weights must be from 1 to 1000",
+                invalid_weights.join(", ")
+            ),
+        });
+    }
+    if parsed_weights.is_empty() {
+        return Err("--weights needs at least one weight".to_string());
+    }
+
+    Ok(parsed_weights)
+}
+
+impl FontFormat {
+    /// Extensions of the font files kept for each font style, most preferred first
+    pub(crate) fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            FontFormat::Woff2 => &["woff2"],
+            FontFormat::Ttf => &["ttf"],
+            FontFormat::Both => &["woff2", "ttf"],
+            FontFormat::Otf => &["otf"],
+        }
+    }
+
+    /// Whether the downloaded files are converted to woff2 files
+    pub(crate) fn has_woff2(&self) -> bool {
+        matches!(self, FontFormat::Woff2 | FontFormat::Both)
+    }
+
+    /// Extension of the files downloaded from google fonts, which are kept as they are with `--format otf`
+    pub(crate) fn source_extension(&self) -> &'static str {
+        match self {
+            FontFormat::Otf => "otf",
+            _ => "ttf",
+        }
+    }
+}
+
+/// Extension of the file a font url points to, e.g. `otf` for `https://fonts.gstatic.com/s/inter/v18/x.otf`
+pub(crate) fn url_extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let (_, extension) = path.rsplit_once('/')?.1.rsplit_once('.')?;
+    Some(extension)
+}
+
+/// Css `format()` of a font file by its extension, e.g. `truetype` for `ttf`
+pub(crate) fn css_format(extension: &str) -> &str {
+    match extension {
+        "ttf" => "truetype",
+        "otf" => "opentype",
+        extension => extension,
+    }
+}
+
+#[derive(Display, EnumString, EnumIter, Clone, Debug, PartialEq, Eq, Hash)]
 #[strum(serialize_all = "kebab-case")]
 pub(crate) enum FontStyles {
     Thin,
@@ -52,6 +309,96 @@ impl FontStyles {
             FontStyles::ThinItalic => ("italic", 100),
         }
     }
+
+    /// Finds the font style with the given numeric weight and style
+    pub(crate) fn from_weight(font_weight: u16, italic: bool) -> Option<FontStyles> {
+        let font_style_name = if italic { "italic" } else { "normal" };
+        FontStyles::iter()
+            .find(|font_style| font_style.get_style_and_weight() == (font_style_name, font_weight))
+    }
+
+    /// Parses the font style back from the suffix of a font file name, e.g. `bold-italic` or `700-italic`.
+    /// Accepts the suffixes of both file naming schemes
+    pub(crate) fn from_filename_suffix(suffix: &str) -> Option<FontStyles> {
+        if let Ok(font_style) = suffix.parse::<FontStyles>() {
+            return Some(font_style);
+        }
+
+        let (font_weight, italic) = match suffix.strip_suffix("-italic") {
+            Some(font_weight) => (font_weight, true),
+            None => (suffix, false),
+        };
+        FontStyles::from_weight(font_weight.parse().ok()?, italic)
+    }
+
+    /// Suffix used in the file names of the font style, e.g. `bold-italic` or `700-italic`
+    pub(crate) fn file_suffix(&self, naming: FileNaming) -> String {
+        match naming {
+            FileNaming::Named => self.to_string(),
+            FileNaming::Numeric => match self.get_style_and_weight() {
+                ("italic", font_weight) => format!("{}-italic", font_weight),
+                (_, font_weight) => font_weight.to_string(),
+            },
+        }
+    }
+
+    /// File name of the font style for a font family, e.g. `inter-bold-italic.woff2`,
+    /// or `bold-italic.woff2` without a family name
+    pub(crate) fn file_name(
+        &self,
+        family_name: &str,
+        naming: FileNaming,
+        extension: &str,
+    ) -> String {
+        self.labeled_file_name(family_name, naming, &BTreeMap::new(), extension)
+    }
+
+    /// File name of the font style like `file_name`, with the style names of `--weight-aliases`
+    /// for named weights, e.g. `inter-book.woff2` with `400` labeled `book`
+    pub(crate) fn labeled_file_name(
+        &self,
+        family_name: &str,
+        naming: FileNaming,
+        weight_labels: &BTreeMap<u16, String>,
+        extension: &str,
+    ) -> String {
+        let file_suffix = match naming {
+            FileNaming::Named => self.label(weight_labels),
+            FileNaming::Numeric => self.file_suffix(naming),
+        };
+        if family_name.is_empty() {
+            return format!("{}.{}", file_suffix, extension);
+        }
+        format!("{}-{}.{}", family_name, file_suffix, extension)
+    }
+
+    /// Label of the font style in generated css, e.g. `semi-bold-italic`. The style name of a weight
+    /// can be overridden, e.g. `600` labeled `demi` gives `demi-italic`
+    pub(crate) fn label(&self, weight_labels: &BTreeMap<u16, String>) -> String {
+        let (font_style_name, font_weight) = self.get_style_and_weight();
+        match weight_labels.get(&font_weight) {
+            Some(weight_label) if font_style_name == "italic" => format!("{}-italic", weight_label),
+            Some(weight_label) => weight_label.clone(),
+            None => self.to_string(),
+        }
+    }
+
+    /// Name of the css module class for the font style, e.g. `bold-italic` becomes `boldItalic`
+    pub(crate) fn class_name(&self, weight_labels: &BTreeMap<u16, String>) -> String {
+        self.label(weight_labels)
+            .split('-')
+            .enumerate()
+            .map(|(idx, word)| {
+                let mut chars = word.chars();
+                match (idx, chars.next()) {
+                    (0, _) | (_, None) => word.to_string(),
+                    (_, Some(first_char)) => {
+                        first_char.to_uppercase().collect::<String>() + chars.as_str()
+                    }
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -64,8 +411,55 @@ pub(crate) struct FontFamily {
     pub family: String,
     pub variants: Vec<String>,
     pub subsets: Vec<String>,
+    /// Font file urls keyed by variant, left out of the cached catalog
+    #[serde(default)]
     pub files: HashMap<String, String>,
     pub category: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(rename = "lastModified", default)]
+    pub last_modified: Option<String>,
+    /// Axes of the variable font, only listed when it is requested with the `VF` capability
+    #[serde(default)]
+    pub axes: Vec<Axis>,
+}
+
+/// Range of a variable font axis, e.g. `wght` from 100 to 900
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Axis {
+    pub tag: String,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl FontFamily {
+    /// Generic css font family to fall back on, derived from the category of the font family
+    pub(crate) fn generic_fallback(&self) -> &'static str {
+        match self.category.as_str() {
+            "serif" => "serif",
+            "monospace" => "monospace",
+            "handwriting" => "cursive",
+            _ => "sans-serif",
+        }
+    }
+
+    /// Fallback stack of `--css-family-stack`, system fonts of the category before its generic font family
+    pub(crate) fn fallback_stack(&self) -> &'static str {
+        match self.category.as_str() {
+            "serif" => "ui-serif, Georgia, Cambria, \"Times New Roman\", serif",
+            "monospace" => "ui-monospace, SFMono-Regular, Menlo, Consolas, monospace",
+            "handwriting" => "cursive",
+            _ => "system-ui, -apple-system, \"Segoe UI\", Roboto, sans-serif",
+        }
+    }
+
+    /// Range of the weight axis of the variable font, if the family has one
+    pub(crate) fn weight_range(&self) -> Option<(u16, u16)> {
+        self.axes
+            .iter()
+            .find(|axis| axis.tag == "wght")
+            .map(|axis| (axis.start as u16, axis.end as u16))
+    }
 }
 
 pub(crate) fn transpile_font_weight(font_string: &str) -> Result<FontStyles, String> {
@@ -96,50 +490,539 @@ pub(crate) fn transpile_font_weight(font_string: &str) -> Result<FontStyles, Str
         .ok_or_else(|| "Couldn't find the variant in the hashmap".to_owned())
 }
 
+/// Font files of a font family, split into static files with a single font style and
+/// variable files that cover a range of an axis
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FontFiles {
+    pub static_files: Vec<(FontStyles, String)>,
+    pub variable_files: Vec<(String, String)>,
+}
+
+/// Whether a variant refers to a variable font file, e.g. `100..900` or `italic[wght]`
+fn is_variable_variant(variant: &str) -> bool {
+    variant.contains("..") || variant.contains('[')
+}
+
+/// Font style a variable font file is installed as, e.g. `italic[wght]` is installed as the italic style
+pub(crate) fn variable_font_style(variant: &str) -> FontStyles {
+    if variant.starts_with("italic") {
+        FontStyles::RegularItalic
+    } else {
+        FontStyles::Regular
+    }
+}
+
+/// Resolves the variants of a font family's files to their font styles.
+/// Variable font files are kept apart from the static ones, and only the first static file
+/// for each font style is kept so the same style is never downloaded twice
+pub(crate) fn resolve_font_files<'a>(
+    files: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<FontFiles, String> {
+    let mut seen_styles = HashSet::new();
+    let mut font_files = FontFiles::default();
+
+    for (variant, url) in files {
+        if is_variable_variant(variant) {
+            font_files
+                .variable_files
+                .push((variant.to_string(), url.to_string()));
+            continue;
+        }
+
+        let font_style = transpile_font_weight(variant)
+            .map_err(|e| format!("Couldn't find variant mapping for {}: {}", variant, e))?;
+
+        if seen_styles.insert(font_style.clone()) {
+            font_files.static_files.push((font_style, url.to_string()));
+        }
+    }
+
+    Ok(font_files)
+}
+
+/// Downloads a variable font file and reads its named instances, e.g. `SemiBold Italic`
+pub(crate) async fn fetch_named_instances(
+    client: &Client,
+    url: &str,
+) -> Result<Vec<NamedInstance>, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let response = client.get(url).send().await.map_err(|_| {
+        record_request("GET", url, None, 0, started.elapsed());
+        format!("Failed to GET from {}", url)
+    })?;
+    let status = response.status();
+    let data = response.bytes().await?;
+    record_request(
+        "GET",
+        url,
+        Some(status),
+        data.len() as u64,
+        started.elapsed(),
+    );
+
+    Ok(Sfnt::parse(&data)?.named_instances()?)
+}
+
+/// Finds the static font style closest to a named instance of a variable font, using its
+/// position on the `wght` axis rounded to the nearest hundred and whether it is italic
+pub(crate) fn font_style_for_instance(named_instance: &NamedInstance) -> Option<FontStyles> {
+    let font_weight = named_instance.coordinate("wght").unwrap_or(400.0);
+    let font_weight = ((font_weight / 100.0).round() * 100.0).clamp(100.0, 900.0) as u16;
+    let italic = named_instance.coordinate("ital").unwrap_or(0.0) >= 0.5
+        || named_instance.coordinate("slnt").unwrap_or(0.0) != 0.0
+        || named_instance.name.to_lowercase().contains("italic");
+
+    FontStyles::from_weight(font_weight, italic)
+}
+
+/// Validates a style name for a weight, e.g. `demi` for `600`. Weights must be 100 to 900 in steps of 100,
+/// and style names may only contain letters, numbers and dashes
+pub(crate) fn parse_weight_label(font_weight: &str, label: &str) -> Result<(u16, String), String> {
+    let font_weight = font_weight
+        .trim()
+        .parse::<u16>()
+        .ok()
+        .filter(|font_weight| (100..=900).contains(font_weight) && font_weight % 100 == 0)
+        .ok_or_else(|| format!("`{}` is not a weight from 100 to 900", font_weight.trim()))?;
+    let label = label.trim().to_lowercase().replace(' ', "-");
+    if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!(
+            "`{}` must only contain letters, numbers and dashes",
+            label
+        ));
+    }
+
+    Ok((font_weight, label))
+}
+
+/// Loads the style names of `--weight-aliases`, one `weight -> label` pair per line, e.g. `400 -> book`.
+/// Since the labels name font files, a label can't be the standard name of another style or be used twice
+pub(crate) fn load_weight_aliases(path: &Path) -> Result<BTreeMap<u16, String>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|_| format!("Could not read weight aliases: {}", path.to_string_lossy()))?;
+
+    let mut weight_labels: BTreeMap<u16, String> = BTreeMap::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (font_weight, label) = line.split_once("->").ok_or_else(|| {
+            format!(
+                "Line {} of {} is not a `weight -> label` pair",
+                idx + 1,
+                path.to_string_lossy()
+            )
+        })?;
+        let (font_weight, label) = parse_weight_label(font_weight, label)
+            .map_err(|err| format!("Line {} of {}: {}", idx + 1, path.to_string_lossy(), err))?;
+
+        let standard_style = FontStyles::iter().find(|font_style| {
+            font_style.to_string() == label || font_style.file_suffix(FileNaming::Numeric) == label
+        });
+        if standard_style
+            .is_some_and(|font_style| font_style.get_style_and_weight().1 != font_weight)
+            || label.ends_with("-italic")
+        {
+            return Err(format!(
+                "Line {} of {}: `{}` is the name of another style",
+                idx + 1,
+                path.to_string_lossy(),
+                label
+            ));
+        }
+        if let Some((other_weight, _)) = weight_labels.iter().find(|(other_weight, other_label)| {
+            **other_label == label && **other_weight != font_weight
+        }) {
+            return Err(format!(
+                "Line {} of {}: `{}` is already the label of {}",
+                idx + 1,
+                path.to_string_lossy(),
+                label,
+                other_weight
+            ));
+        }
+        weight_labels.insert(font_weight, label);
+    }
+
+    Ok(weight_labels)
+}
+
+/// Common misspellings, condensed forms and renamed fonts, and their names on google fonts
+const BUILTIN_FAMILY_ALIASES: &[(&str, &str)] = &[
+    ("opensans", "Open Sans"),
+    ("open sans", "Open Sans"),
+    ("roboto mono", "Roboto Mono"),
+    ("robotomono", "Roboto Mono"),
+    ("robotoslab", "Roboto Slab"),
+    ("roboto slab", "Roboto Slab"),
+    ("dmsans", "DM Sans"),
+    ("dm sans", "DM Sans"),
+    ("ibmplexsans", "IBM Plex Sans"),
+    ("ibm plex sans", "IBM Plex Sans"),
+    ("ibmplexmono", "IBM Plex Mono"),
+    ("ibm plex mono", "IBM Plex Mono"),
+    ("jetbrainsmono", "JetBrains Mono"),
+    ("jetbrains mono", "JetBrains Mono"),
+    ("sourcecodepro", "Source Code Pro"),
+    ("source code pro", "Source Code Pro"),
+    ("source sans pro", "Source Sans 3"),
+    ("sourcesanspro", "Source Sans 3"),
+    ("playfair", "Playfair Display"),
+    ("playfairdisplay", "Playfair Display"),
+    ("montserat", "Montserrat"),
+    ("poppin", "Poppins"),
+    ("lato", "Lato"),
+    ("inter", "Inter"),
+];
+
+/// Alternative names of font families, resolved to the name google fonts uses before querying the API.
+/// Made up of the built in aliases, the aliases in `~/.gfontapi/aliases` and the ones passed with `--family-alias`
+#[derive(Debug, Default, Clone)]
+pub(crate) struct FamilyAliases {
+    /// Font family names keyed by the normalized alias
+    aliases: HashMap<String, String>,
+}
+
+/// Normalizes an alias for lookups, ignoring case and repeated whitespace
+fn normalize_alias(alias: &str) -> String {
+    alias
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+impl FamilyAliases {
+    /// Loads the built in aliases along with the user's aliases from the aliases file, one
+    /// `alias -> Font Name` pair per line, and the command line. Later aliases take precedence
+    pub(crate) fn load(
+        aliases_path: &Path,
+        cli_aliases: &[(String, String)],
+    ) -> Result<FamilyAliases, String> {
+        let mut aliases: HashMap<String, String> = BUILTIN_FAMILY_ALIASES
+            .iter()
+            .map(|(alias, font_family)| (alias.to_string(), font_family.to_string()))
+            .collect();
+
+        if let Ok(contents) = std::fs::read_to_string(aliases_path) {
+            for (idx, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let (alias, font_family) = line
+                    .split_once("->")
+                    .filter(|(alias, font_family)| {
+                        !alias.trim().is_empty() && !font_family.trim().is_empty()
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "Line {} of {} is not an `alias -> Font Name` pair",
+                            idx + 1,
+                            aliases_path.to_string_lossy()
+                        )
+                    })?;
+                aliases.insert(normalize_alias(alias), font_family.trim().to_string());
+            }
+        }
+
+        for (alias, font_family) in cli_aliases {
+            aliases.insert(normalize_alias(alias), font_family.clone());
+        }
+
+        Ok(FamilyAliases { aliases })
+    }
+
+    /// Resolves an alias to the name of the font family on google fonts,
+    /// names that aren't aliases are returned unchanged
+    pub(crate) fn resolve<'a>(&'a self, font_name: &'a str) -> &'a str {
+        self.aliases
+            .get(&normalize_alias(font_name))
+            .map(String::as_str)
+            .unwrap_or(font_name)
+    }
+}
+
+/// Fetches a font family from the API. The `files` link to ttf files unless a `capability`
+/// such as `WOFF2` asks for another format. A response that can't be parsed is fetched once
+/// more with `--refetch-on-parse-error`, since it was most likely cut off
 pub(crate) async fn fetch_font_data(
     client: &Client,
-    api_key: &str,
+    api: &ApiConfig,
     font_name: &str,
+    capability: Option<&str>,
+    family_index: usize,
+    retries: u32,
+    aliases: &FamilyAliases,
 ) -> Result<FontFamily, Box<dyn std::error::Error>> {
-    let api_url = format!(
+    let font_name = aliases.resolve(font_name);
+    let mut api_url = format!(
         "{base_url}?key={key}&family={fontname}",
-        base_url = BASE_URL,
-        key = api_key,
+        base_url = api.base_url,
+        key = api.key,
         fontname = font_name
     );
+    if let Some(capability) = capability {
+        api_url.push_str(&format!("&capability={}", capability));
+    }
 
-    let response = client
-        .get(&api_url)
-        .send()
-        .await
-        .map_err(|err| {
-            eprintln!(
-                "{}: Failed to fetch `{}`\n  {}: {}",
-                "error".red(),
-                &api_url,
-                "Caused by".red(),
-                err
+    let fetch_font = || async {
+        let (status, body) = fetch_text_with_retries(client.get(&api_url), &api_url, retries)
+            .await
+            .map_err(|err| format!("Failed to fetch `{}`: {}", font_name, err.without_url()))?;
+        if status != StatusCode::OK {
+            return Err(format!("Failed to fetch `{}`: {}", font_name, status));
+        }
+        Ok(serde_json::from_str::<Font>(&body))
+    };
+
+    let mut font_data = fetch_font().await?;
+    if font_data.is_err() && api.refetch_on_parse_error {
+        if api.verbose {
+            println!(
+                "{}",
+                format!(
+                    "Could not parse the response for `{}`, fetching it again",
+                    font_name
+                )
+                .dimmed()
             );
-            process::exit(1);
-        })
+        }
+        font_data = fetch_font().await?;
+    }
+    let font_data =
+        font_data.map_err(|_| format!("Could not parse the response for `{}`", font_name))?;
+
+    let match_count = font_data.items.len();
+    match font_data.items.into_iter().nth(family_index) {
+        Some(font_family) => Ok(font_family),
+        None if match_count == 0 => Err(format!("No font family named `{}`", font_name).into()),
+        None => Err(format!(
+            "`{}` matched {} font families, --family-index {} is out of range (0 to {})",
+            font_name,
+            match_count,
+            family_index,
+            match_count - 1
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_filename_suffix_round_trips_every_style() {
+        for font_style in FontStyles::iter() {
+            for naming in [FileNaming::Named, FileNaming::Numeric] {
+                assert_eq!(
+                    FontStyles::from_filename_suffix(&font_style.file_suffix(naming)),
+                    Some(font_style.clone())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_weight_breaks_ties_like_css() {
+        let available = [300, 400, 700];
+
+        assert_eq!(nearest_weight(&available, 350), Some(300));
+        assert_eq!(nearest_weight(&available, 380), Some(400));
+        assert_eq!(nearest_weight(&available, 520), Some(400));
+        assert_eq!(nearest_weight(&available, 550), Some(700));
+        assert_eq!(nearest_weight(&[400, 600], 500), Some(400));
+        assert_eq!(nearest_weight(&[500, 700], 600), Some(700));
+        assert_eq!(nearest_weight(&[], 400), None);
+    }
+
+    #[test]
+    fn parse_weights_trims_dedupes_and_lists_invalid_weights() {
+        let weights = |weights: &[&str]| -> Vec<String> {
+            weights.iter().map(|weight| weight.to_string()).collect()
+        };
+
+        assert_eq!(
+            parse_weights(
+                &weights(&[" 700", "400 ", "700", ""]),
+                WeightFallback::Strict
+            ),
+            Ok(vec![700, 400])
+        );
+        assert_eq!(
+            parse_weights(&weights(&["350", "400", "bold", "1000"]), WeightFallback::Strict)
+                .unwrap_err(),
+            "Invalid weights `350`, `bold`, `1000`, weights must be 100 to 900 in steps of 100 (pass --weight-fallback nearest to use the nearest available weight for others)"
+        );
+        assert_eq!(
+            parse_weights(&weights(&["350", "1000"]), WeightFallback::Nearest),
+            Ok(vec![350, 1000])
+        );
+        assert!(parse_weights(&weights(&["", " "]), WeightFallback::Strict).is_err());
+    }
+
+    #[test]
+    fn family_from_specimen_url_decodes_spaces() {
+        assert_eq!(
+            family_from_specimen_url("https://fonts.google.com/specimen/Inter"),
+            Ok("Inter".to_string())
+        );
+        assert_eq!(
+            family_from_specimen_url("https://fonts.google.com/specimen/Open+Sans?query=open"),
+            Ok("Open Sans".to_string())
+        );
+        assert_eq!(
+            family_from_specimen_url("fonts.google.com/specimen/Roboto%20Mono/tester#about"),
+            Ok("Roboto Mono".to_string())
+        );
+        assert!(family_from_specimen_url("https://fonts.google.com/specimen/").is_err());
+        assert!(family_from_specimen_url("https://example.com/specimen/Inter").is_err());
+    }
+
+    #[test]
+    fn load_weight_aliases_rejects_labels_of_other_styles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weight-aliases");
+
+        std::fs::write(&path, "# labels\n400 -> Book\n700 -> bold\n").unwrap();
+        let weight_labels = load_weight_aliases(&path).unwrap();
+        assert_eq!(weight_labels.get(&400).map(String::as_str), Some("book"));
+        assert_eq!(
+            FontStyles::RegularItalic.labeled_file_name(
+                "inter",
+                FileNaming::Named,
+                &weight_labels,
+                "woff2"
+            ),
+            "inter-book-italic.woff2"
+        );
+
+        std::fs::write(&path, "400 -> bold\n").unwrap();
+        assert!(load_weight_aliases(&path)
+            .unwrap_err()
+            .contains("`bold` is the name of another style"));
+        std::fs::write(&path, "450 -> book\n").unwrap();
+        assert!(load_weight_aliases(&path)
+            .unwrap_err()
+            .contains("`450` is not a weight from 100 to 900"));
+        std::fs::write(&path, "300 -> book\n400 -> book\n").unwrap();
+        assert!(load_weight_aliases(&path)
+            .unwrap_err()
+            .contains("`book` is already the label of 300"));
+    }
+
+    #[test]
+    fn family_aliases_resolve_ignoring_case_and_whitespace() {
+        let aliases = FamilyAliases::load(
+            Path::new("/nonexistent/aliases"),
+            &[("sans".to_string(), "Open Sans".to_string())],
+        )
         .unwrap();
 
-    if response.status() != StatusCode::OK {
-        let status = response.status();
-        eprintln!(
-            "{}: Failed to fetch `{}`\n  {}: {}",
-            "error".red(),
-            &api_url,
-            "Caused by".red(),
-            status
+        assert_eq!(aliases.resolve("OpenSans"), "Open Sans");
+        assert_eq!(aliases.resolve("  roboto   MONO "), "Roboto Mono");
+        assert_eq!(aliases.resolve("Sans"), "Open Sans");
+        assert_eq!(aliases.resolve("Inconsolata"), "Inconsolata");
+    }
+
+    #[test]
+    fn from_filename_suffix_rejects_unknown_suffixes() {
+        assert_eq!(FontStyles::from_filename_suffix("heavy"), None);
+        assert_eq!(FontStyles::from_filename_suffix("450"), None);
+        assert_eq!(FontStyles::from_filename_suffix("bold-oblique"), None);
+    }
+
+    #[test]
+    fn file_suffix_uses_numeric_weights() {
+        assert_eq!(FontStyles::Regular.file_suffix(FileNaming::Numeric), "400");
+        assert_eq!(
+            FontStyles::RegularItalic.file_suffix(FileNaming::Numeric),
+            "400-italic"
+        );
+        assert_eq!(
+            FontStyles::BoldItalic.file_suffix(FileNaming::Numeric),
+            "700-italic"
+        );
+        assert_eq!(
+            FontStyles::BoldItalic.file_suffix(FileNaming::Named),
+            "bold-italic"
+        );
+    }
+
+    #[test]
+    fn resolve_font_files_skips_duplicate_styles() {
+        let files = [
+            ("regular", "https://fonts.gstatic.com/inter-regular.ttf"),
+            ("700", "https://fonts.gstatic.com/inter-bold.ttf"),
+            (
+                "regular",
+                "https://fonts.gstatic.com/inter-regular-copy.ttf",
+            ),
+        ];
+
+        let font_files = resolve_font_files(files).unwrap();
+
+        assert_eq!(
+            font_files.static_files,
+            vec![
+                (
+                    FontStyles::Regular,
+                    "https://fonts.gstatic.com/inter-regular.ttf".to_string()
+                ),
+                (
+                    FontStyles::Bold,
+                    "https://fonts.gstatic.com/inter-bold.ttf".to_string()
+                ),
+            ]
         );
-        process::exit(1);
     }
 
-    let body = response.text().await?;
-    let font_data: Font = serde_json::from_str(&body)
-        .map_err(|_| eprintln!("Could not parse response"))
+    #[test]
+    fn resolve_font_files_separates_variable_files() {
+        let font_data: Font =
+            serde_json::from_str(include_str!("../tests/fixtures/mixed_files.json")).unwrap();
+        let font_family = &font_data.items[0];
+
+        let font_files = resolve_font_files(
+            font_family
+                .files
+                .iter()
+                .map(|(variant, url)| (variant.as_str(), url.as_str())),
+        )
         .unwrap();
 
-    Ok(font_data.items[0].clone())
+        let mut static_styles: Vec<String> = font_files
+            .static_files
+            .iter()
+            .map(|(font_style, _)| font_style.to_string())
+            .collect();
+        static_styles.sort();
+        let mut variable_variants: Vec<&str> = font_files
+            .variable_files
+            .iter()
+            .map(|(variant, _)| variant.as_str())
+            .collect();
+        variable_variants.sort();
+
+        assert_eq!(static_styles, vec!["bold", "regular", "regular-italic"]);
+        assert_eq!(variable_variants, vec!["100..900", "italic[wght]"]);
+    }
+
+    #[test]
+    fn url_extension_ignores_the_query_and_directories() {
+        assert_eq!(
+            url_extension("https://fonts.gstatic.com/s/inter/v18/inter.otf?v=2"),
+            Some("otf")
+        );
+        assert_eq!(
+            url_extension("https://fonts.gstatic.com/s/inter.v18/UcCO3Fwr"),
+            None
+        );
+        assert_eq!(css_format("otf"), "opentype");
+        assert_eq!(css_format("woff2"), "woff2");
+    }
 }
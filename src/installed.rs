@@ -0,0 +1,104 @@
+use indicatif::HumanBytes;
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{
+    lockfile::Lockfile,
+    utils::{format_font_string, scan_font_styles},
+};
+
+/// A font family installed to the target directory
+#[derive(Debug, Serialize)]
+pub(crate) struct InstalledFamily {
+    pub family: String,
+    /// Name of the font directory
+    pub directory: String,
+    pub files: Vec<InstalledFile>,
+    /// Total size of the font files in bytes
+    pub size: u64,
+}
+
+/// A woff2 file of an installed font family
+#[derive(Debug, Serialize)]
+pub(crate) struct InstalledFile {
+    /// Font style parsed from the file name, e.g. `bold-italic`
+    pub style: String,
+    pub font_weight: u16,
+    pub font_style: &'static str,
+    pub file: String,
+    /// Size of the file in bytes
+    pub size: u64,
+}
+
+/// Finds the font families installed to the target directory from the woff2 files in each font directory.
+/// The family names are taken from the lockfile when the family is locked
+pub(crate) fn find_installed_families(output_dir: &Path) -> Result<Vec<InstalledFamily>, String> {
+    let lockfile = Lockfile::load(output_dir)?;
+    let entries = std::fs::read_dir(output_dir)
+        .map_err(|_| format!("Could not read directory: {}", output_dir.to_string_lossy()))?;
+
+    let mut installed_families = vec![];
+    for entry in entries.flatten().filter(|entry| entry.path().is_dir()) {
+        let directory = entry.file_name().to_string_lossy().to_string();
+        let (font_styles, naming, filename_style) =
+            scan_font_styles(&entry.path(), &directory, "woff2")?;
+        if font_styles.is_empty() {
+            continue;
+        }
+
+        let files: Vec<InstalledFile> = font_styles
+            .iter()
+            .map(|font_style| {
+                let (font_style_name, font_weight) = font_style.get_style_and_weight();
+                let file = font_style.file_name(filename_style.prefix(&directory), naming, "woff2");
+                let size = std::fs::metadata(entry.path().join(&file))
+                    .map(|metadata| metadata.len())
+                    .unwrap_or_default();
+
+                InstalledFile {
+                    style: font_style.to_string(),
+                    font_weight,
+                    font_style: font_style_name,
+                    file,
+                    size,
+                }
+            })
+            .collect();
+
+        installed_families.push(InstalledFamily {
+            family: lockfile
+                .families
+                .get(&directory)
+                .map(|locked| locked.family.clone())
+                .unwrap_or_else(|| format_font_string(&directory)),
+            size: files.iter().map(|file| file.size).sum(),
+            directory,
+            files,
+        });
+    }
+    installed_families.sort_by(|a, b| a.directory.cmp(&b.directory));
+
+    Ok(installed_families)
+}
+
+/// Prints each installed font family with its styles and total size
+pub(crate) fn print_installed_families(installed_families: &[InstalledFamily]) {
+    for installed_family in installed_families {
+        println!(
+            " {} {}",
+            &installed_family.family,
+            format!(
+                "({}, {})",
+                installed_family
+                    .files
+                    .iter()
+                    .map(|file| file.style.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", "),
+                HumanBytes(installed_family.size)
+            )
+            .dimmed()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::fonts::FontFamily;
+
+/// Name of the lockfile written to the target directory
+pub(crate) const LOCKFILE_NAME: &str = "gfontapi.lock";
+
+/// Records the upstream version of every font family installed to a target directory,
+/// so later runs can detect when google fonts has changed a family
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Lockfile {
+    pub families: BTreeMap<String, LockedFamily>,
+}
+
+/// Upstream version of an installed font family
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LockedFamily {
+    pub family: String,
+    pub version: Option<String>,
+    pub last_modified: Option<String>,
+    /// Installed font files, keyed by file name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, LockedFile>,
+}
+
+/// Where an installed font file was downloaded from and its SHA-256 checksums, as lowercase hex
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LockedFile {
+    /// Url the file was downloaded from after following redirects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Checksum of the file downloaded from google fonts, before conversion
+    pub source_sha256: String,
+    /// Checksum of the converted file in the font directory
+    pub sha256: String,
+}
+
+impl From<&FontFamily> for LockedFamily {
+    fn from(font_family: &FontFamily) -> Self {
+        LockedFamily {
+            family: font_family.family.clone(),
+            version: font_family.version.clone(),
+            last_modified: font_family.last_modified.clone(),
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl LockedFamily {
+    /// Describes how the upstream font family differs from the locked one, if it does
+    pub(crate) fn drift(&self, upstream: &LockedFamily) -> Option<String> {
+        if self.version == upstream.version && self.last_modified == upstream.last_modified {
+            return None;
+        }
+
+        Some(format!(
+            "{} changed upstream from {} ({}) to {} ({})",
+            self.family,
+            self.version.as_deref().unwrap_or("unknown version"),
+            self.last_modified.as_deref().unwrap_or("unknown date"),
+            upstream.version.as_deref().unwrap_or("unknown version"),
+            upstream.last_modified.as_deref().unwrap_or("unknown date"),
+        ))
+    }
+}
+
+impl Lockfile {
+    /// Loads the lockfile from the target directory, an empty lockfile is returned when there is none
+    pub(crate) fn load(output_dir: &Path) -> Result<Lockfile, String> {
+        let lockfile_path = output_dir.join(LOCKFILE_NAME);
+        if !lockfile_path.exists() {
+            return Ok(Lockfile::default());
+        }
+
+        let contents = std::fs::read_to_string(&lockfile_path)
+            .map_err(|_| format!("Could not read lockfile: {:?}", lockfile_path))?;
+        serde_json::from_str(&contents)
+            .map_err(|_| format!("Could not parse lockfile: {:?}", lockfile_path))
+    }
+
+    /// Writes the lockfile to the target directory
+    pub(crate) fn save(&self, output_dir: &Path) -> Result<(), String> {
+        let lockfile_path = output_dir.join(LOCKFILE_NAME);
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|_| "Could not serialize lockfile".to_string())?;
+
+        std::fs::write(&lockfile_path, contents + "\n")
+            .map_err(|_| format!("Could not create file at path: {:?}", lockfile_path))
+    }
+}
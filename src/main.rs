@@ -1,131 +1,2922 @@
+pub mod audit;
+pub mod batch;
+pub mod bundle;
+pub mod catalog;
+pub mod css2;
+pub mod doctor;
+pub mod error;
 pub mod fonts;
+pub mod installed;
+pub mod lockfile;
+pub mod probe;
+pub mod quota;
+pub mod sfnt;
+pub mod store;
 pub mod utils;
 
-use clap::Parser;
-use fonts::{fetch_font_data, transpile_font_weight, FontFamily, FontStyles};
+use audit::{open_audit_log, record_request};
+use batch::BatchState;
+use bundle::{export_bundle, import_bundle};
+use catalog::{
+    list_families, load_catalog, print_cached_catalog, print_catalog_age, print_catalog_file,
+    random_family, read_catalog_file, search_families, Catalog, CatalogCache,
+};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use css2::{fetch_css2_faces, resolve_subsets, subset_file_name, Css2Face};
+use doctor::run_doctor;
+use error::GfontError;
+use fonts::{
+    family_from_specimen_url, fetch_font_data, fetch_named_instances, font_style_for_instance,
+    load_weight_aliases, nearest_weight, parse_weight_label, parse_weights, resolve_font_files,
+    url_extension, variable_font_style, FamilyAliases, FileNaming, FilenameStyle, FontFamily,
+    FontFormat, FontStyles, Layout, WeightFallback, WeightsPreset,
+};
 use futures::{stream::FuturesUnordered, StreamExt};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use installed::{find_installed_families, print_installed_families};
+use lockfile::{LockedFamily, LockedFile, Lockfile, LOCKFILE_NAME};
 use owo_colors::OwoColorize;
-use reqwest::Client;
+use probe::run_probe;
+use quota::{print_quota, record_quota_headers};
+use regex::Regex;
+use reqwest::{
+    header::{CONTENT_LENGTH, LOCATION},
+    redirect::Policy,
+    Client,
+};
+use sfnt::{strip_metadata, FontMetrics, Sfnt};
+use sha2::{Digest, Sha256};
 use std::{
+    collections::{BTreeMap, HashMap},
+    env,
     fs::File,
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    process,
     sync::{Arc, Mutex},
-    time::Instant,
+    time::{Duration, Instant},
+};
+use store::BlobStore;
+use strum::IntoStaticStr;
+use tokio::sync::Semaphore;
+use utils::{
+    confirm, convert_to_woff2, disk_full_message, ensure_dir_path, find_dir_differing_by_case,
+    format_font_string, get_api_key, get_gfontapi_dir, get_output_dir, get_with_retries,
+    is_disk_full, parse_css_font_styles, prune_font_files, read_family_file_map,
+    read_family_name_map, read_font_list, redirect_policy, scan_font_styles, sha256_file,
+    sha256_hex, slugify, validate_css, verify_woff2, write_css2_file_for_font,
+    write_css_file_for_font, write_css_module_for_font, write_index_css,
+    write_json_manifest_for_font, write_metrics_json_for_font, write_preview_for_font,
+    write_preview_index, write_remote_css_file_for_font, ApiConfig, CssOptions, CssOrder, CssQuote,
+    CssSource, Interaction, LineEnding, MappedFamily, PreviewTheme, SlugStyle,
 };
-use utils::{convert_to_woff2, get_api_key, get_output_dir, write_css_file_for_font};
 
-const BASE_URL: &str = "https://www.googleapis.com/webfonts/v1/webfonts";
+const BASE_URL: &str = "https://www.googleapis.com/webfonts/v1/webfonts";
+
+/// Stylesheet formats that can be generated for a font family
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// A global `fonts.css` file with an `@font-face` rule per font style
+    Css,
+    /// A `fonts.module.css` file with a scoped class per font style and a `.d.ts` declaration
+    CssModules,
+}
+
+impl OutputFormat {
+    fn file_name(&self) -> &'static str {
+        match self {
+            OutputFormat::Css => "fonts.css",
+            OutputFormat::CssModules => "fonts.module.css",
+        }
+    }
+}
+
+/// Number of font files that are downloaded and converted at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Jobs {
+    /// Derive the limits from the machine, see `Jobs::limits`
+    Auto,
+    /// The same fixed limit for downloads and conversions
+    Fixed(usize),
+}
+
+impl Jobs {
+    /// Limits on the number of concurrent downloads and conversions.
+    /// `auto` allows one conversion per available cpu since `woff2_compress` is cpu bound,
+    /// and 6 downloads, the same number of connections per host that browsers open
+    fn limits(&self) -> (usize, usize) {
+        match self {
+            Jobs::Auto => {
+                let cpu_count = std::thread::available_parallelism()
+                    .map(|count| count.get())
+                    .unwrap_or(1);
+                (6, cpu_count)
+            }
+            Jobs::Fixed(jobs) => (*jobs, *jobs),
+        }
+    }
+}
+
+/// Parses `--jobs` as either `auto` or a positive number
+fn parse_jobs(jobs: &str) -> Result<Jobs, String> {
+    if jobs == "auto" {
+        return Ok(Jobs::Auto);
+    }
+
+    match jobs.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(Jobs::Fixed(jobs)),
+        _ => Err(format!("`{}` must be `auto` or a positive number", jobs)),
+    }
+}
+
+/// Characters used to draw the progress spinner and bars
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ProgressChars {
+    /// A braille spinner
+    Unicode,
+    /// Plain ascii, for terminals that can't render braille and for logs
+    Ascii,
+}
+
+impl ProgressChars {
+    /// Spinner frames, the last one is shown once the spinner finishes
+    fn tick_chars(&self) -> &'static str {
+        match self {
+            ProgressChars::Unicode => "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏",
+            ProgressChars::Ascii => "|/-\\ ",
+        }
+    }
+
+    /// Filled, current and empty characters of the bars
+    fn progress_chars(&self) -> &'static str {
+        match self {
+            ProgressChars::Unicode => "--",
+            ProgressChars::Ascii => "=> ",
+        }
+    }
+}
+
+/// Colors the progress bars can be drawn in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ProgressColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+/// Why a font file could not be downloaded and converted
+#[derive(Debug)]
+enum FileError {
+    /// The disk ran out of space, so the remaining files can't be written either
+    DiskFull(String),
+    /// The file could not be downloaded
+    Failed(String),
+    /// The file was downloaded but could not be converted to a valid woff2 file
+    ConversionFailed(String),
+    /// The downloaded file does not match the checksum recorded in the lockfile
+    ChecksumMismatch(String),
+}
+
+impl From<String> for FileError {
+    fn from(message: String) -> Self {
+        FileError::Failed(message)
+    }
+}
+
+/// A font file to download for a font style
+#[derive(Clone, Debug)]
+struct DownloadFile {
+    font_style: FontStyles,
+    url: String,
+    /// Subset of a woff2 file from the css2 API, which is saved as is instead of being converted
+    subset: Option<String>,
+}
+
+/// Options controlling how the font files of a font family are downloaded and converted
+#[derive(Clone, Debug)]
+struct DownloadOptions {
+    naming: FileNaming,
+    jobs: Jobs,
+    /// Check that every converted file is a valid woff2 file
+    verify_woff2: bool,
+    /// Print the url each file was downloaded from
+    verbose: bool,
+    progress_chars: ProgressChars,
+    progress_color: ProgressColor,
+    /// Show a single bar for the font family instead of a bar per file
+    family_progress: bool,
+    /// Font file formats kept in the font directory
+    font_format: FontFormat,
+    /// Number of times a failed download is retried
+    retries: u32,
+    /// How long `woff2_compress` may run for a single file
+    convert_timeout: Duration,
+    /// Remove the tables and name records fonts render without before converting
+    strip_metadata: bool,
+    /// Whether the font file names start with the font family name
+    filename_style: FilenameStyle,
+    layout: Layout,
+    /// Style names of `--weight-aliases` used in the font file names
+    weight_labels: BTreeMap<u16, String>,
+    /// Read the metrics of each ttf file before converting it, for `--explicit-metrics` and `--metrics-json`
+    read_metrics: bool,
+    /// Store the downloaded files are copied from and added to, with `--store`
+    store: Option<BlobStore>,
+    /// Checksums of the downloaded files recorded in the lockfile, keyed by the lockfile entry of the
+    /// file. Downloads that don't match them are refused, empty with `--update-lock`
+    locked_sources: BTreeMap<String, String>,
+    /// Report the files that failed as single lines of json
+    json_errors: bool,
+}
+
+impl DownloadOptions {
+    /// Name of the file a font file is downloaded to, before `--layout` places it in a directory
+    fn file_name(
+        &self,
+        font_style: &FontStyles,
+        subset: Option<&str>,
+        family_name: &str,
+    ) -> String {
+        match subset {
+            Some(subset) => subset_file_name(
+                font_style,
+                self.filename_style.prefix(family_name),
+                self.naming,
+                subset,
+            ),
+            None => font_style.labeled_file_name(
+                self.filename_style.prefix(family_name),
+                self.naming,
+                &self.weight_labels,
+                self.font_format.source_extension(),
+            ),
+        }
+    }
+}
+
+struct ProgressState {
+    downloaded_count: u16,
+    downloaded_files: Vec<FontStyles>,
+    /// Lockfile entries of the converted files, keyed by file name
+    locked_files: BTreeMap<String, LockedFile>,
+    /// Bytes removed from the downloaded files by `--strip-metadata`
+    stripped_bytes: u64,
+    metrics: HashMap<FontStyles, FontMetrics>,
+}
+
+/// Outcome of downloading the font files of a font family
+struct DownloadResults {
+    downloaded_files: Vec<FontStyles>,
+    /// Font styles, with their subset, whose download failed
+    failed_files: Vec<(FontStyles, Option<String>)>,
+    /// Font styles, with their subset, that were downloaded but whose conversion failed
+    failed_conversions: Vec<(FontStyles, Option<String>)>,
+    /// Number of downloaded files that did not match the checksum recorded in the lockfile
+    checksum_mismatches: usize,
+    /// Lockfile entries of the converted files, keyed by file name
+    locked_files: BTreeMap<String, LockedFile>,
+    /// Bytes removed from the downloaded files by `--strip-metadata`
+    stripped_bytes: u64,
+    /// Metrics of the ttf file of each font style, read with `--explicit-metrics` and `--metrics-json`
+    metrics: HashMap<FontStyles, FontMetrics>,
+}
+
+/// Options shared by the commands that read the catalog
+#[derive(clap::Args)]
+struct CatalogArgs {
+    /// Fetch the catalog again instead of using the cached one
+    #[arg(
+        long = "refresh-catalog",
+        help = "fetch the catalog again instead of using the cached one."
+    )]
+    refresh_catalog: bool,
+    /// Read the catalog from a local json file instead of the API
+    #[arg(
+        long = "catalog-file",
+        value_name = "file",
+        conflicts_with = "refresh_catalog",
+        help = "read the catalog from a json file in the format of the api response, without network access or an api key."
+    )]
+    catalog_file: Option<PathBuf>,
+}
+
+/// Commands for browsing the google fonts catalog and managing downloaded fonts
+#[derive(Subcommand, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+enum Command {
+    /// List every font family in the catalog
+    List {
+        /// Only list the font families whose name matches a regular expression
+        #[arg(
+            long = "regex",
+            value_name = "pattern",
+            value_parser = parse_regex,
+            help = "only list the font families whose name matches a regular expression, e.g. \"^Noto\" or \"Mono$\"."
+        )]
+        regex: Option<Regex>,
+        /// Only list the font families in a category
+        #[arg(
+            long = "category",
+            help = "only list the font families in a category, e.g. serif, sans-serif, display, handwriting or monospace."
+        )]
+        category: Option<String>,
+        #[command(flatten)]
+        catalog_args: CatalogArgs,
+    },
+    /// Search the catalog for font families by name
+    Search {
+        /// Part of the font family name
+        #[arg(value_name = "query")]
+        query: String,
+        #[command(flatten)]
+        catalog_args: CatalogArgs,
+    },
+    /// Pick a random font family from the catalog
+    Random {
+        #[command(flatten)]
+        catalog_args: CatalogArgs,
+    },
+    /// Fetch the catalog and cache it, so later catalog commands don't have to
+    Prefetch,
+    /// Show the details of a font family, including the named instances of its variable fonts
+    Info {
+        /// Name of the font
+        #[arg(value_name = "fontname")]
+        fontname: String,
+        /// Read the font family from a local json file instead of the API
+        #[arg(
+            long = "catalog-file",
+            value_name = "file",
+            help = "read the font family from a json file in the format of the api response instead of the api."
+        )]
+        catalog_file: Option<PathBuf>,
+    },
+    /// List the font families installed to the target directory
+    Installed {
+        /// Print the installed font families as json
+        #[arg(
+            long = "json",
+            help = "print the installed font families, styles and sizes as json."
+        )]
+        json: bool,
+    },
+    /// Check the installed font files against the checksums recorded in the lockfile
+    Verify,
+    /// Check that woff2_compress, the api key and the gfontapi directory are set up
+    Doctor {
+        /// Offer to fix each problem that is found
+        #[arg(
+            long = "fix",
+            help = "offer to create ~/.gfontapi/bin, link woff2_compress into it and write a starter aliases file."
+        )]
+        fix: bool,
+    },
+    /// Make a single request to the api to check the network, TLS, proxy and api key
+    Probe,
+    /// Package an installed font into a bundle that can be imported without network access
+    Export {
+        /// Bundle to write, e.g. inter.gfbundle
+        #[arg(value_name = "bundle")]
+        bundle: PathBuf,
+        /// Font directory in the target directory to package, the name of the bundle without its extension by default
+        #[arg(
+            long = "family",
+            help = "font directory in the target directory to package, defaults to the bundle name without its extension."
+        )]
+        family: Option<String>,
+    },
+    /// Install a font from a bundle created with export, without network access
+    Import {
+        /// Bundle to install, e.g. inter.gfbundle
+        #[arg(value_name = "bundle")]
+        bundle: PathBuf,
+    },
+    /// Regenerate the fonts.css file of an already downloaded font from its woff2 files
+    Css {
+        /// Directory of the downloaded font, e.g. ./fonts/inter/
+        #[arg(value_name = "font_dir")]
+        font_dir: PathBuf,
+    },
+}
+
+// TODO: Separate into commands := add, remove, compress (some people might prefer ttf idk)
+// TODO: add, remove := specific weights, styles
+// TODO: Add colors to CLI output
+#[derive(Parser)]
+#[command(name = "gfontapi")]
+#[command(version = "0.1.0")]
+#[command(about = "Manage all your google fonts from the terminal.")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("weight_filter").multiple(true)))]
+#[command(
+    help_template = "{about}\n\nUsage: {name} [OPTIONS] \"[fontname]\"\n       {name} <COMMAND>\n\nCommands\n{subcommands}\n\nOptions\n{options}"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Name of the font to download
+    #[arg(
+        value_name = "fontname",
+        required_unless_present_any = ["from_file", "family", "family_from_url", "emit_woff2_from_ttf_dir"]
+    )]
+    fontname: Option<String>,
+    /// Directory of ttf files to convert to woff2 and write the css for
+    #[arg(
+        long = "emit-woff2-from-ttf-dir",
+        value_name = "ttf_dir",
+        conflicts_with_all = ["fontname", "family", "from_file"],
+        help_heading = "options",
+        help = "convert the {family}-{style}.ttf files of a directory to woff2 and write their fonts.css, without any network access."
+    )]
+    emit_woff2_from_ttf_dir: Option<PathBuf>,
+    /// Which of the font families the API matches to install
+    #[arg(
+        long = "family-index",
+        default_value_t = 0,
+        help_heading = "options",
+        help = "install the nth font family the api matches for the font name, counting from 0, when the name is ambiguous."
+    )]
+    family_index: usize,
+    /// Name of the font to download, instead of the positional fontname
+    #[arg(
+        long = "family",
+        conflicts_with_all = ["fontname", "from_file"],
+        help_heading = "options",
+        help = "name of the font to download, an explicit alternative to the positional fontname for scripts."
+    )]
+    family: Option<String>,
+    /// Google fonts specimen url of the font to download
+    #[arg(
+        long = "family-from-url",
+        value_name = "URL",
+        value_parser = family_from_specimen_url,
+        conflicts_with_all = ["fontname", "family", "from_file"],
+        help_heading = "options",
+        help = "google fonts specimen url of the font to download, e.g. https://fonts.google.com/specimen/Open+Sans."
+    )]
+    family_from_url: Option<String>,
+    /// File with the names of the fonts to download, one per line
+    #[arg(
+        long = "from-file",
+        conflicts_with = "fontname",
+        help_heading = "options",
+        help = "download every font listed in a file, one font name per line."
+    )]
+    from_file: Option<PathBuf>,
+    /// Continue an interrupted batch install
+    #[arg(
+        long = "resume",
+        requires = "from_file",
+        help_heading = "options",
+        help = "skip the fonts an interrupted --from-file batch already installed."
+    )]
+    resume: bool,
+    /// Skip fonts that were already downloaded to the target directory
+    #[arg(
+        long = "only-new",
+        help_heading = "options",
+        help = "skip fonts that already have a directory in the target directory."
+    )]
+    only_new: bool,
+    /// Write an index.css importing the stylesheet of every font family
+    #[arg(
+        long = "index-css",
+        help_heading = "options",
+        help = "write an index.css to the target directory that imports every font's fonts.css."
+    )]
+    index_css: bool,
+    /// Directory to place the converted fonts
+    #[arg(
+        short,
+        long = "target-dir",
+        help_heading = "options",
+        help = "target directory, defaults to ./fonts.",
+        global = true
+    )]
+    target_dir: Option<PathBuf>,
+    /// Users google application API key
+    #[arg(
+        short,
+        long = "api-key",
+        help_heading = "options",
+        help = "google api key generated from developer console, can also be set as `EXPORT GFONT_API_KEY=<API_KEY>`",
+        global = true
+    )]
+    api_key: Option<String>,
+    /// Url of the developer API, overridden by the tests to use a mock server
+    #[arg(long = "api-base-url", default_value = BASE_URL, hide = true, global = true)]
+    api_base_url: String,
+    /// Format of the generated stylesheet
+    #[arg(
+        long = "output-format",
+        value_enum,
+        default_value_t = OutputFormat::Css,
+        help_heading = "options",
+        help = "stylesheet to generate, css-modules also writes a typescript declaration file."
+    )]
+    output_format: OutputFormat,
+    /// Naming scheme of the font files
+    #[arg(
+        long = "naming",
+        value_enum,
+        default_value_t = FileNaming::Named,
+        help_heading = "options",
+        help = "font file naming, named (inter-bold-italic.woff2) or numeric (inter-700-italic.woff2)."
+    )]
+    naming: FileNaming,
+    /// Remove font files that are not part of this download
+    #[arg(
+        long = "prune",
+        help_heading = "options",
+        help = "remove font files left in the font directory by previous downloads."
+    )]
+    prune: bool,
+    /// Fallback font families used in generated font stacks
+    #[arg(
+        long = "fallback",
+        help_heading = "options",
+        help = "fallback font families for generated font stacks, defaults to a generic family based on the font category."
+    )]
+    fallback: Option<String>,
+    /// Print the urls of the font files without downloading anything
+    #[arg(
+        long = "print-url",
+        help_heading = "options",
+        help = "print the url of each font file that would be downloaded and exit."
+    )]
+    print_url: bool,
+    /// Order of the `@font-face` rules in the generated css
+    #[arg(
+        long = "order",
+        value_enum,
+        default_value_t = CssOrder::Weights,
+        help_heading = "options",
+        help = "order of the @font-face rules in the generated css, by ascending weight or alphabetically by style name."
+    )]
+    order: CssOrder,
+    /// Quotes around the font family name in the generated css
+    #[arg(
+        long = "quote",
+        alias = "css-font-family-quote",
+        value_enum,
+        default_value_t = CssQuote::Double,
+        help_heading = "options",
+        help = "quotes around the font family name in the generated css, auto leaves them out for names that don't need them."
+    )]
+    quote: CssQuote,
+    /// Line terminator of the generated css
+    #[arg(
+        long = "line-ending",
+        alias = "css-line-ending",
+        value_enum,
+        default_value_t = LineEnding::Native,
+        help_heading = "options",
+        help = "line terminator of the generated css, native uses crlf on windows and lf everywhere else."
+    )]
+    line_ending: LineEnding,
+    /// Leave the stylesheet alone when its content would not change
+    #[arg(
+        long = "skip-css-if-unchanged",
+        help_heading = "options",
+        help = "don't write the generated css when the existing file already has the same content, so file watchers don't fire on re-runs."
+    )]
+    skip_css_if_unchanged: bool,
+    /// Check that the generated css parses
+    #[arg(
+        long = "validate-css",
+        help_heading = "options",
+        help = "parse the generated css after writing it and fail when it is not valid css, e.g. an unclosed string or a missing semicolon."
+    )]
+    validate_css: bool,
+    /// Sources of the `src` of each `@font-face` rule, in order
+    #[arg(
+        long = "css-src-order",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with_all = ["subsets", "css2_text", "emit_fontface_only", "download_only"],
+        help_heading = "options",
+        help = "sources of the src of each @font-face rule in this exact order, e.g. local,woff2,ttf."
+    )]
+    css_src_order: Vec<CssSource>,
+    /// Pin the vertical metrics of each `@font-face` rule to the metrics of its font file
+    #[arg(
+        long = "explicit-metrics",
+        conflicts_with_all = ["subsets", "css2_text", "emit_fontface_only", "download_only"],
+        help_heading = "options",
+        help = "read the vertical metrics of each ttf file and add ascent-override, descent-override and line-gap-override to its @font-face rule."
+    )]
+    explicit_metrics: bool,
+    /// Write the metrics of each font file to metrics.json
+    #[arg(
+        long = "metrics-json",
+        conflicts_with_all = ["subsets", "css2_text", "emit_fontface_only"],
+        help_heading = "options",
+        help = "read the units per em, ascent, descent, line gap, x-height and cap height of each ttf file and write them to metrics.json in the font directory."
+    )]
+    metrics_json: bool,
+    /// Write a json manifest of the font files next to the css
+    #[arg(
+        long = "emit-json-and-css",
+        conflicts_with_all = ["subsets", "css2_text", "emit_fontface_only", "download_only"],
+        help_heading = "options",
+        help = "write a fonts.json manifest of the @font-face rules next to fonts.css, both generated from the same downloaded files."
+    )]
+    emit_json_and_css: bool,
+    /// Write css that loads the fonts from google fonts instead of downloading them
+    #[arg(
+        long = "emit-fontface-only",
+        help_heading = "options",
+        help = "write css whose @font-face rules load the woff2 files hosted by google fonts, without downloading anything.",
+        conflicts_with_all = ["subsets", "css2_text", "print_url", "estimate", "preview", "prune"]
+    )]
+    emit_fontface_only: bool,
+    /// Estimate the download size without downloading anything
+    #[arg(
+        long = "estimate",
+        help_heading = "options",
+        help = "print the total download size of the font files and exit."
+    )]
+    estimate: bool,
+    /// List the font styles that would be downloaded and ask before downloading them
+    #[arg(
+        long = "list-weights",
+        conflicts_with_all = ["print_url", "estimate"],
+        help_heading = "options",
+        help = "list the font styles left after --weights and the other filters and ask for confirmation before downloading them, --yes downloads them without asking."
+    )]
+    list_weights: bool,
+    /// Server path the generated css urls are rooted at
+    #[arg(
+        long = "css-base-url",
+        value_parser = parse_css_base_url,
+        help_heading = "options",
+        help = "server path the font urls in the generated css are rooted at, e.g. /assets/fonts/inter/."
+    )]
+    css_base_url: Option<String>,
+    /// Directory to write the stylesheets to instead of the font directories
+    #[arg(
+        long = "css-dir",
+        help_heading = "options",
+        help = "write each font's stylesheet to this directory, named after the font, e.g. ./css/inter.css."
+    )]
+    css_dir: Option<PathBuf>,
+    /// Directory the font urls in the generated css are relative to
+    #[arg(
+        long = "output-relative-to",
+        value_name = "DIR",
+        conflicts_with = "css_base_url",
+        help_heading = "options",
+        help = "directory the font urls in the generated css are relative to, e.g. the web root the css is served from."
+    )]
+    output_relative_to: Option<PathBuf>,
+    /// Verify the woff2 files after conversion
+    #[arg(
+        long = "verify-woff2",
+        help_heading = "options",
+        help = "check that every converted file is a valid, non-empty woff2 file."
+    )]
+    verify_woff2: bool,
+    /// Write an html preview of the font
+    #[arg(
+        long = "preview",
+        help_heading = "options",
+        help = "write a preview.html specimen page to the font directory."
+    )]
+    preview: bool,
+    /// Color theme of the html preview
+    #[arg(
+        long = "theme",
+        value_enum,
+        default_value_t = PreviewTheme::Light,
+        requires = "preview",
+        help_heading = "options",
+        help = "color theme of the preview page."
+    )]
+    theme: PreviewTheme,
+    /// Check installed fonts against the lockfile without downloading
+    #[arg(
+        long = "check",
+        help_heading = "options",
+        help = "check whether the fonts changed upstream since they were locked in gfontapi.lock, without downloading."
+    )]
+    check: bool,
+    /// Number of concurrent downloads and conversions
+    #[arg(
+        short,
+        long = "jobs",
+        value_parser = parse_jobs,
+        default_value = "auto",
+        help_heading = "options",
+        help = "number of font files downloaded and converted at once, auto uses 6 downloads and one conversion per cpu."
+    )]
+    jobs: Jobs,
+    /// Stylesheet to read the font weights and styles to download from
+    #[arg(
+        long = "weights-from-css",
+        help_heading = "options",
+        help = "only download the weights and styles used by the font's @font-face rules in an existing stylesheet."
+    )]
+    weights_from_css: Option<PathBuf>,
+    /// Font weights to download
+    #[arg(
+        long = "weights",
+        value_delimiter = ',',
+        conflicts_with = "only_variable",
+        help_heading = "options",
+        help = "only download these weights in both the normal and italic style, e.g. 300,400,700.",
+        group = "weight_filter"
+    )]
+    weights: Vec<String>,
+    /// Common set of weights to download
+    #[arg(
+        long = "weights-preset",
+        value_enum,
+        conflicts_with = "only_variable",
+        help_heading = "options",
+        help = "only download a common set of weights, text (400,500,700), display (400,700,900) or minimal (400,700), along with any --weights.",
+        group = "weight_filter"
+    )]
+    weights_preset: Option<WeightsPreset>,
+    /// Font styles to download
+    #[arg(
+        long = "select",
+        value_delimiter = ',',
+        value_parser = parse_style_selector,
+        conflicts_with_all = ["weights", "weights_preset", "only_variable"],
+        help_heading = "options",
+        help = "only download these styles, weights with a trailing i for italic, e.g. 400,700,700i."
+    )]
+    select: Vec<FontStyles>,
+    /// What to do with weights that are not available
+    #[arg(
+        long = "weight-fallback",
+        value_enum,
+        default_value_t = WeightFallback::Strict,
+        requires = "weight_filter",
+        help_heading = "options",
+        help = "drop weights that are not available with a warning (strict), or use the files of the nearest available weight for them (nearest)."
+    )]
+    weight_fallback: WeightFallback,
+    /// Fail when a weight is not available instead of dropping it
+    #[arg(
+        long = "abort-on-missing-weight",
+        requires = "weight_filter",
+        conflicts_with = "weight_fallback",
+        help_heading = "options",
+        help = "fail before downloading anything when a weight passed to --weights or --weights-preset is not available, instead of dropping it with a warning."
+    )]
+    abort_on_missing_weight: bool,
+    /// Named instance of the variable font to download
+    #[arg(
+        long = "instance",
+        help_heading = "options",
+        help = "only download the static font matching a named instance of the variable font, e.g. \"SemiBold Italic\"."
+    )]
+    instance: Option<String>,
+    /// Only install the variable font of a family
+    #[arg(
+        long = "only-variable",
+        help_heading = "options",
+        help = "install the variable font of each family and fail for families without one, instead of downloading their static weights.",
+        conflicts_with_all = ["instance", "subsets", "css2_text", "emit_fontface_only"]
+    )]
+    only_variable: bool,
+    /// Keep installing the remaining fonts when one fails
+    #[arg(
+        long = "ignore-errors",
+        help_heading = "options",
+        help = "continue with the remaining fonts when one fails, then report every failure and exit with an error."
+    )]
+    ignore_errors: bool,
+    /// File mapping font names to the names of their directories and files
+    #[arg(
+        long = "family-file-map",
+        help_heading = "options",
+        help = "file mapping each font to the name of its directory and files, one `Inter -> ui-font` pair per line."
+    )]
+    family_file_map: Option<PathBuf>,
+    /// File mapping font names to the name in the css and the names of their directories and files
+    #[arg(
+        long = "family-name-map",
+        conflicts_with = "family_file_map",
+        help_heading = "options",
+        help = "file mapping each font to the font-family name in the generated css and the name of its directory and files, one `Inter -> Brand Sans -> brand-sans` entry per line."
+    )]
+    family_name_map: Option<PathBuf>,
+    /// Write an html page linking the preview of every font family
+    #[arg(
+        long = "include-preview-index",
+        requires = "preview",
+        help_heading = "options",
+        help = "write an index.html to the target directory linking every font's preview page and stylesheet."
+    )]
+    include_preview_index: bool,
+    /// Fail instead of following redirects
+    #[arg(
+        long = "no-redirects",
+        help_heading = "options",
+        help = "fail when a request is redirected instead of following the redirect.",
+        global = true
+    )]
+    no_redirects: bool,
+    /// Maximum number of redirects followed for a request
+    #[arg(
+        long = "max-redirects",
+        default_value_t = 10,
+        conflicts_with = "no_redirects",
+        help_heading = "options",
+        help = "maximum number of redirects followed for a request before it fails, redirect loops fail right away.",
+        global = true
+    )]
+    max_redirects: usize,
+    /// Print details about each download
+    #[arg(
+        short,
+        long = "verbose",
+        help_heading = "options",
+        help = "print the url each font file was downloaded from after following redirects, and the api quota after the run."
+    )]
+    verbose: bool,
+    /// Print errors as json for scripts
+    #[arg(
+        long = "json-errors",
+        help_heading = "options",
+        help = "print each error to stderr as a single line of json with a code, message and context, normal output still goes to stdout.",
+        global = true
+    )]
+    json_errors: bool,
+    /// Add utility classes for each font style to the generated css
+    #[arg(
+        long = "utility-classes",
+        help_heading = "options",
+        help = "add a class setting the font family, style and weight for each style to the generated css, e.g. .font-inter-bold."
+    )]
+    utility_classes: bool,
+    /// Declare the full font stack of the font family in a css variable
+    #[arg(
+        long = "css-family-stack",
+        help_heading = "options",
+        help = "declare the font family with a fallback stack of system fonts for its category in a css variable, e.g. --font-inter, --fallback overrides the stack."
+    )]
+    css_family_stack: bool,
+    /// Label each `@font-face` rule with a comment
+    #[arg(
+        long = "css-comment-per-face",
+        help_heading = "options",
+        help = "prefix each @font-face rule with a comment naming its style, e.g. /* Inter Bold Italic (700) */."
+    )]
+    css_comment_per_face: bool,
+    /// Style names to use for weights in generated css
+    #[arg(
+        long = "css-weight-override",
+        value_parser = parse_css_weight_override,
+        value_delimiter = ',',
+        help_heading = "options",
+        help = "style names to use for weights in css comments and class names, e.g. 600=demi,800=heavy."
+    )]
+    css_weight_override: Vec<(u16, String)>,
+    /// File of style names to use for weights in file names and generated css
+    #[arg(
+        long = "weight-aliases",
+        value_name = "file",
+        help_heading = "options",
+        help = "file of style names for weights, one `weight -> label` pair per line, e.g. `400 -> book`. The labels are used in font file names, css comments and class names, --css-weight-override takes precedence in the css."
+    )]
+    weight_aliases: Option<PathBuf>,
+    /// Aliases of font family names
+    #[arg(
+        long = "family-alias",
+        value_parser = parse_family_alias,
+        help_heading = "options",
+        help = "resolve an alias to a font name before querying google fonts, e.g. \"sans=Open Sans\". Aliases can also be listed in ~/.gfontapi/aliases, one `alias -> Font Name` pair per line.",
+        global = true
+    )]
+    family_alias: Vec<(String, String)>,
+    /// Category the font is expected to be in
+    #[arg(
+        long = "category",
+        help_heading = "options",
+        help = "category the font is expected to be in, e.g. serif, sans-serif, display, handwriting or monospace."
+    )]
+    category: Option<String>,
+    /// Require the font name to match the font family exactly
+    #[arg(
+        long = "family-case-sensitive",
+        help_heading = "options",
+        help = "fail unless the font name matches the name of the font family on google fonts exactly, including its case."
+    )]
+    family_case_sensitive: bool,
+    /// Fail when the font is not in the expected category
+    #[arg(
+        long = "strict-category",
+        requires = "category",
+        help_heading = "options",
+        help = "fail instead of warning when the font is not in the --category."
+    )]
+    strict_category: bool,
+    /// Characters used to draw the progress spinner and bars
+    #[arg(
+        long = "progress-style",
+        value_enum,
+        default_value_t = ProgressChars::Unicode,
+        help_heading = "options",
+        help = "characters used to draw the progress spinner and bars, ascii for terminals that can't render braille."
+    )]
+    progress_style: ProgressChars,
+    /// Color of the progress bars
+    #[arg(
+        long = "progress-color",
+        value_enum,
+        default_value_t = ProgressColor::Green,
+        help_heading = "options",
+        help = "color of the download progress bars."
+    )]
+    progress_color: ProgressColor,
+    /// Show one progress line per font family instead of a bar per file
+    #[arg(
+        long = "family-progress",
+        alias = "parallel-families-progress",
+        help_heading = "options",
+        help = "show a single progress bar per font family with the number of files done, instead of a bar per file, for large batches."
+    )]
+    family_progress: bool,
+    /// Answer yes to every prompt
+    #[arg(
+        short = 'y',
+        long = "yes",
+        alias = "assume-yes",
+        conflicts_with = "no_interactive",
+        help_heading = "options",
+        help = "answer yes to every prompt, e.g. confirming which files --prune removes.",
+        global = true
+    )]
+    assume_yes: bool,
+    /// Fail instead of prompting
+    #[arg(
+        long = "no-interactive",
+        help_heading = "options",
+        help = "fail instead of prompting when something needs to be confirmed.",
+        global = true
+    )]
+    no_interactive: bool,
+    /// Font file formats to keep
+    #[arg(
+        long = "format",
+        value_enum,
+        default_value_t = FontFormat::Woff2,
+        help_heading = "options",
+        help = "font files to keep, woff2 for the web, ttf for desktop, both, or otf for families distributed as OpenType."
+    )]
+    font_format: FontFormat,
+    /// Only download the ttf files
+    #[arg(
+        long = "download-only",
+        conflicts_with_all = ["font_format", "output_woff2_only_css", "subsets", "css2_text", "emit_fontface_only", "preview"],
+        help_heading = "options",
+        help = "only download the ttf files, without converting them or writing css, e.g. to install them on your desktop."
+    )]
+    download_only: bool,
+    /// Whether the font file names start with the font family name
+    #[arg(
+        long = "filename-style",
+        value_enum,
+        default_value_t = FilenameStyle::WithFamily,
+        help_heading = "options",
+        help = "name the font files after the family and style, e.g. inter-bold.woff2, or only the style, e.g. bold.woff2."
+    )]
+    filename_style: FilenameStyle,
+    /// Layout of the font files in the font directory
+    #[arg(
+        long = "layout",
+        value_enum,
+        default_value_t = Layout::Flat,
+        conflicts_with_all = ["subsets", "css2_text", "emit_fontface_only", "prune"],
+        help_heading = "options",
+        help = "lay out the font files directly in the font directory, or in a directory for each weight, e.g. inter/400/inter-regular.woff2."
+    )]
+    layout: Layout,
+    /// Strip the tables and name records fonts render without
+    #[arg(
+        long = "strip-metadata",
+        help_heading = "options",
+        help = "remove the digital signature, font editor tables and descriptive name records from the ttf files before converting them."
+    )]
+    strip_metadata: bool,
+    /// Replace locked font files whose checksums changed
+    #[arg(
+        long = "update-lock",
+        help_heading = "options",
+        help = "accept downloaded font files that don't match the checksums in gfontapi.lock and record their new checksums, instead of refusing to install them."
+    )]
+    update_lock: bool,
+    /// Reuse font files downloaded by earlier installs on this machine
+    #[arg(
+        long = "store",
+        help_heading = "options",
+        help = "keep downloaded font files in a content-addressed store in $XDG_CACHE_HOME/gfontapi, and copy files already in it instead of downloading them again."
+    )]
+    store: bool,
+    /// Only reference the woff2 files in the stylesheet
+    #[arg(
+        long = "output-woff2-only-css",
+        help_heading = "options",
+        help = "only reference the woff2 files in the generated css, even when ttf files are kept with --format both."
+    )]
+    output_woff2_only_css: bool,
+    /// Unicode subsets to download as separate files
+    #[arg(
+        long = "subsets",
+        value_delimiter = ',',
+        conflicts_with = "preview",
+        help_heading = "options",
+        help = "download woff2 files split into unicode subsets from the css2 API, e.g. latin,latin-ext, or all for every subset."
+    )]
+    subsets: Option<Vec<String>>,
+    /// Text whose glyphs are the only ones downloaded
+    #[arg(
+        long = "css2-text",
+        conflicts_with_all = ["subsets", "preview"],
+        help_heading = "options",
+        help = "download woff2 files from the css2 API containing only the glyphs of the text, e.g. \"Company Name\"."
+    )]
+    css2_text: Option<String>,
+    /// Casing of the directory and file names
+    #[arg(
+        long = "slug-style",
+        value_enum,
+        default_value_t = SlugStyle::Kebab,
+        help_heading = "options",
+        help = "casing of the font directory and file names, kebab (dm-sans), snake (dm_sans) or pascal (DmSans)."
+    )]
+    slug_style: SlugStyle,
+    /// Number of times a failed request is retried
+    #[arg(
+        long = "retries",
+        default_value_t = 3,
+        help_heading = "options",
+        help = "number of times a request that failed with a network or server error is retried, with an increasing delay.",
+        global = true
+    )]
+    retries: u32,
+    /// Delay between fetching font families
+    #[arg(
+        long = "family-delay-ms",
+        alias = "download-throttle-families",
+        value_name = "ms",
+        default_value_t = 0,
+        help_heading = "options",
+        help = "milliseconds to wait before fetching each font family after the first, to space out the api requests of large --from-file batches."
+    )]
+    family_delay_ms: u64,
+    /// Fetch a font family again when its response can't be parsed
+    #[arg(
+        long = "refetch-on-parse-error",
+        help_heading = "options",
+        help = "fetch a font family from the api once more when its response can't be parsed, e.g. when it was cut off by a flaky connection.",
+        global = true
+    )]
+    refetch_on_parse_error: bool,
+    /// Skip TLS certificate validation
+    #[arg(
+        long = "danger-accept-invalid-certs",
+        help_heading = "options",
+        help = "DANGER: accept invalid TLS certificates, e.g. behind an intercepting proxy. Downloads can then be tampered with.",
+        global = true
+    )]
+    danger_accept_invalid_certs: bool,
+    /// File to record every network request in
+    #[arg(
+        long = "audit-log",
+        value_name = "FILE",
+        help_heading = "options",
+        help = "append a json line for every network request to a file, with its url, status, size and duration. The api key is redacted.",
+        global = true
+    )]
+    audit_log: Option<PathBuf>,
+    /// Maximum time to establish a connection
+    #[arg(
+        long = "connect-timeout",
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help_heading = "options",
+        help = "seconds to wait for a connection to be established before the request fails.",
+        global = true
+    )]
+    connect_timeout: u64,
+    /// Maximum time for a whole request
+    #[arg(
+        long = "timeout",
+        value_name = "SECONDS",
+        help_heading = "options",
+        help = "seconds a whole request may take, including the connection and downloading the body. No limit by default.",
+        global = true
+    )]
+    timeout: Option<u64>,
+    /// Bypass every cache
+    #[arg(
+        long = "no-cache",
+        help_heading = "options",
+        help = "neither read nor write the cached catalog, without deleting it.",
+        global = true
+    )]
+    no_cache: bool,
+    /// Maximum time a woff2 conversion may take
+    #[arg(
+        long = "convert-timeout",
+        value_name = "SECONDS",
+        default_value_t = 60,
+        help_heading = "options",
+        help = "seconds woff2_compress may run for a single file before it is killed and the file is marked as failed."
+    )]
+    convert_timeout: u64,
+}
+
+impl Args {
+    /// How the cached catalog is used, from `--no-cache` and `--refresh-catalog`
+    fn catalog_cache(&self, catalog_args: &CatalogArgs) -> CatalogCache {
+        if self.no_cache {
+            CatalogCache::Bypass
+        } else if catalog_args.refresh_catalog {
+            CatalogCache::Refresh
+        } else {
+            CatalogCache::Use
+        }
+    }
+
+    /// Loads the catalog for a catalog command from `--catalog-file`, or from the cache or the API,
+    /// and prints where it came from
+    async fn catalog(
+        &self,
+        client: &Client,
+        catalog_args: &CatalogArgs,
+    ) -> Result<Catalog, Box<dyn std::error::Error>> {
+        if let Some(catalog_file) = &catalog_args.catalog_file {
+            let catalog = read_catalog_file(catalog_file)?;
+            print_catalog_file(catalog_file);
+            return Ok(catalog);
+        }
+
+        let catalog = load_catalog(
+            client,
+            &self.api()?,
+            self.catalog_cache(catalog_args),
+            self.retries,
+        )
+        .await?;
+        print_catalog_age(&catalog);
+        Ok(catalog)
+    }
+
+    /// Url and key to call the developer API with, fails when no api key is set
+    fn api(&self) -> Result<ApiConfig, GfontError> {
+        Ok(ApiConfig {
+            base_url: self.api_base_url.clone(),
+            key: get_api_key(self.api_key.clone())?,
+            refetch_on_parse_error: self.refetch_on_parse_error,
+            verbose: self.verbose,
+        })
+    }
+
+    /// Weights of `--weights-preset` followed by the ones passed to `--weights`, validated against
+    /// `--weight-fallback`. Empty when neither is passed
+    fn weights(&self) -> Result<Vec<u16>, String> {
+        let mut weights = self
+            .weights_preset
+            .map(|weights_preset| weights_preset.weights().to_vec())
+            .unwrap_or_default();
+        if !self.weights.is_empty() {
+            for font_weight in parse_weights(&self.weights, self.weight_fallback)? {
+                if !weights.contains(&font_weight) {
+                    weights.push(font_weight);
+                }
+            }
+        }
+
+        Ok(weights)
+    }
+
+    /// Style names of `--weight-aliases` used in the font file names, empty when it is not passed
+    fn weight_aliases(&self) -> Result<BTreeMap<u16, String>, String> {
+        match &self.weight_aliases {
+            Some(weight_aliases) => load_weight_aliases(weight_aliases),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Font file formats to keep, `--download-only` keeps the ttf files
+    fn font_format(&self) -> FontFormat {
+        if self.download_only {
+            FontFormat::Ttf
+        } else {
+            self.font_format
+        }
+    }
+
+    /// Waits `--family-delay-ms` before fetching every font family but the first of a batch
+    async fn wait_between_families(&self, fetched_count: usize) {
+        if fetched_count > 0 && self.family_delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(self.family_delay_ms)).await;
+        }
+    }
+
+    /// Font files referenced by the generated css, `--output-woff2-only-css` leaves out the ttf files
+    fn css_font_format(&self) -> FontFormat {
+        if self.output_woff2_only_css {
+            FontFormat::Woff2
+        } else {
+            self.font_format()
+        }
+    }
+
+    /// How prompts are answered, from `--yes` and `--no-interactive`
+    fn interaction(&self) -> Interaction {
+        if self.assume_yes {
+            Interaction::AssumeYes
+        } else if self.no_interactive {
+            Interaction::NoInteractive
+        } else {
+            Interaction::Prompt
+        }
+    }
+}
+
+/// Parses an `alias=Font Name` pair of `--family-alias`
+fn parse_family_alias(family_alias: &str) -> Result<(String, String), String> {
+    match family_alias.split_once('=') {
+        Some((alias, font_family))
+            if !alias.trim().is_empty() && !font_family.trim().is_empty() =>
+        {
+            Ok((alias.trim().to_string(), font_family.trim().to_string()))
+        }
+        _ => Err(format!(
+            "`{}` must be an alias=Font Name pair",
+            family_alias
+        )),
+    }
+}
+
+/// Parses a `weight=label` pair of `--css-weight-override`, e.g. `600=demi`
+fn parse_css_weight_override(weight_label: &str) -> Result<(u16, String), String> {
+    let (font_weight, label) = weight_label
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` must be a weight=label pair", weight_label))?;
+    parse_weight_label(font_weight, label)
+}
+
+/// Parses a style of `--select`, a weight with a trailing `i` for italic, e.g. `700i`
+fn parse_style_selector(selector: &str) -> Result<FontStyles, String> {
+    let selector = selector.trim();
+    let (font_weight, italic) = match selector.strip_suffix('i') {
+        Some(font_weight) => (font_weight, true),
+        None => (selector, false),
+    };
+
+    font_weight
+        .parse::<u16>()
+        .ok()
+        .and_then(|font_weight| FontStyles::from_weight(font_weight, italic))
+        .ok_or_else(|| {
+            format!(
+                "`{}` must be a weight from 100 to 900 in steps of 100, with a trailing i for italic",
+                selector
+            )
+        })
+}
+
+/// Compiles the font family name pattern of `list --regex`
+fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern)
+        .map_err(|err| format!("`{}` is not a valid regular expression: {}", pattern, err))
+}
+
+/// Validates that the css base url ends with a `/` so font file names can be appended to it
+fn parse_css_base_url(base_url: &str) -> Result<String, String> {
+    if !base_url.ends_with('/') {
+        return Err(format!("`{}` must end with a `/`", base_url));
+    }
+    Ok(base_url.to_string())
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let json_errors = args.json_errors;
+    let command: &'static str = args
+        .command
+        .as_ref()
+        .map_or("install", |command| command.into());
+    let err = match run(args).await.map_err(|err| err.downcast::<GfontError>()) {
+        Ok(()) => return,
+        Err(Ok(err)) => *err,
+        Err(Err(err)) => GfontError::Failed {
+            command: command.to_string(),
+            message: err.to_string(),
+        },
+    };
+    err.report(json_errors);
+    process::exit(1);
+}
+
+async fn run(mut args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let aliases = FamilyAliases::load(&get_gfontapi_dir().join("aliases"), &args.family_alias)?;
+
+    let output_dir = get_output_dir(args.target_dir.clone())?;
+    if let Some(audit_log) = &args.audit_log {
+        open_audit_log(audit_log)?;
+    }
+    let redirect_policy = if args.no_redirects {
+        Policy::none()
+    } else {
+        redirect_policy(args.max_redirects)
+    };
+    if args.danger_accept_invalid_certs {
+        eprintln!(
+            "{}: {}\n  {}",
+            "warning".yellow(),
+            "TLS certificate validation is disabled by --danger-accept-invalid-certs"
+                .yellow()
+                .bold(),
+            "Anyone between you and google fonts can read and tamper with the downloaded fonts, only use this behind a proxy you trust"
+                .dimmed()
+        );
+    }
+    let mut client_builder = reqwest::Client::builder()
+        .redirect(redirect_policy)
+        .danger_accept_invalid_certs(args.danger_accept_invalid_certs)
+        .connect_timeout(Duration::from_secs(args.connect_timeout));
+    if let Some(timeout) = args.timeout {
+        client_builder = client_builder.timeout(Duration::from_secs(timeout));
+    }
+    let client = client_builder.build()?;
+
+    if let Some(command) = args.command.take() {
+        return run_command(&client, &args, &aliases, &output_dir, command).await;
+    }
+    if let Some(ttf_dir) = &args.emit_woff2_from_ttf_dir {
+        return compress_ttf_dir(ttf_dir, Duration::from_secs(args.convert_timeout));
+    }
+
+    let api = args.api()?;
+
+    if (args.subsets.is_some() || args.css2_text.is_some())
+        && (args.font_format != FontFormat::Woff2 || args.output_format != OutputFormat::Css)
+    {
+        return Err(
+            "--subsets and --css2-text only support woff2 files and the css output format".into(),
+        );
+    }
+    args.weights()?;
+    args.weight_aliases()?;
+    if let Some(output_relative_to) = &args.output_relative_to {
+        if !output_relative_to.is_dir() {
+            return Err(format!(
+                "--output-relative-to directory does not exist: {}",
+                output_relative_to.to_string_lossy()
+            )
+            .into());
+        }
+    }
+    for (idx, source) in args.css_src_order.iter().enumerate() {
+        let source_name = source.to_possible_value().unwrap().get_name().to_string();
+        if args.css_src_order[..idx].contains(source) {
+            return Err(format!("--css-src-order lists {} more than once", source_name).into());
+        }
+        if let Some(extension) = source.extension() {
+            if !args.css_font_format().extensions().contains(&extension) {
+                return Err(format!(
+                    "--css-src-order lists {}, but the generated css doesn't reference {} files",
+                    source_name, extension
+                )
+                .into());
+            }
+        }
+    }
+    if args.emit_fontface_only && args.output_format != OutputFormat::Css {
+        return Err("--emit-fontface-only only supports the css output format".into());
+    }
+    if args.emit_json_and_css && args.output_format != OutputFormat::Css {
+        return Err("--emit-json-and-css only supports the css output format".into());
+    }
+    if args.output_woff2_only_css && !args.font_format.has_woff2() {
+        return Err(format!(
+            "--output-woff2-only-css needs woff2 files, which --format {} does not keep",
+            args.font_format.to_possible_value().unwrap().get_name()
+        )
+        .into());
+    }
+
+    let fontnames = match &args.from_file {
+        Some(from_file) => read_font_list(from_file)?,
+        None => vec![args
+            .fontname
+            .clone()
+            .or_else(|| args.family.clone())
+            .or_else(|| args.family_from_url.clone())
+            .unwrap()],
+    };
+
+    let mapped_families = match (&args.family_file_map, &args.family_name_map) {
+        (Some(family_file_map), _) => resolve_family_map(
+            &fontnames,
+            read_family_file_map(family_file_map)?
+                .into_iter()
+                .map(|(fontname, output_name)| {
+                    let mapped_family = MappedFamily {
+                        display_name: None,
+                        output_name,
+                    };
+                    (fontname, mapped_family)
+                })
+                .collect(),
+            "family file map",
+        )?,
+        (None, Some(family_name_map)) => resolve_family_map(
+            &fontnames,
+            read_family_name_map(family_name_map)?,
+            "family name map",
+        )?,
+        (None, None) => HashMap::new(),
+    };
+    let mapped_family = |fontname: &str| mapped_families.get(&fontname.to_lowercase()).cloned();
+    let output_name = |fontname: &str| mapped_family(fontname).map(|mapped| mapped.output_name);
+
+    if args.check {
+        let lockfile = Lockfile::load(&output_dir)?;
+        let mut changed_count = 0;
+        for (idx, fontname) in fontnames.iter().enumerate() {
+            args.wait_between_families(idx).await;
+            if !check_font_family(
+                &client,
+                &api,
+                fontname,
+                output_name(fontname),
+                &lockfile,
+                &args,
+                &aliases,
+            )
+            .await
+            {
+                changed_count += 1;
+            }
+        }
+        if changed_count > 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Dry runs don't install anything, so they leave the progress of a batch alone
+    let mut batch_state = match &args.from_file {
+        Some(from_file) if !args.estimate && !args.print_url => {
+            let from_file = from_file
+                .canonicalize()
+                .unwrap_or_else(|_| from_file.clone());
+            if args.resume {
+                Some(BatchState::load(&output_dir, &from_file)?)
+            } else {
+                Some(BatchState {
+                    from_file,
+                    completed: vec![],
+                })
+            }
+        }
+        _ => None,
+    };
+
+    let mut skipped_count = 0;
+    let mut installed_count = 0;
+    let mut failures = vec![];
+    let mut fetched_count = 0;
+    for fontname in &fontnames {
+        if batch_state
+            .as_ref()
+            .is_some_and(|batch_state| batch_state.is_completed(fontname))
+        {
+            println!(
+                "{} {}",
+                "Skipping already installed in this batch".dimmed(),
+                fontname.cyan()
+            );
+            skipped_count += 1;
+            continue;
+        }
+        let family_name = output_name(fontname)
+            .unwrap_or_else(|| slugify(aliases.resolve(fontname), args.slug_style));
+        let is_installed = output_dir.join(&family_name).is_dir()
+            || find_dir_differing_by_case(&output_dir, &family_name).is_some();
+        if args.only_new && is_installed {
+            println!(
+                "{} {}",
+                "Skipping already installed".dimmed(),
+                fontname.cyan()
+            );
+            skipped_count += 1;
+            continue;
+        }
+
+        // Skipped fonts aren't fetched, so they don't wait either
+        args.wait_between_families(fetched_count).await;
+        fetched_count += 1;
+        if let Err(err) = install_font_family(
+            &client,
+            &api,
+            fontname,
+            mapped_family(fontname),
+            &output_dir,
+            &args,
+            &aliases,
+        )
+        .await
+        {
+            GfontError::InstallFailed {
+                family: fontname.clone(),
+                message: err.to_string(),
+            }
+            .report(args.json_errors);
+            if !args.ignore_errors {
+                process::exit(1);
+            }
+            failures.push((fontname, err.to_string()));
+            continue;
+        }
+        installed_count += 1;
+        if let Some(batch_state) = &mut batch_state {
+            if let Err(err) = batch_state.complete(&output_dir, fontname) {
+                eprintln!(
+                    "{}: Could not save the progress of the batch\n  {}: {}",
+                    "warning".yellow(),
+                    "Caused by".yellow(),
+                    err
+                );
+            }
+        }
+    }
+
+    // Failed fonts are retried by the next --resume
+    if batch_state.is_some() && failures.is_empty() {
+        BatchState::remove(&output_dir)?;
+    }
+
+    if args.from_file.is_some() {
+        println!(
+            "{}",
+            format!(
+                "Skipped {} font families, installed {}",
+                skipped_count, installed_count
+            )
+            .dimmed()
+        );
+    }
+
+    // With --json-errors each failure was already printed as it happened
+    if !failures.is_empty() && !args.json_errors {
+        eprintln!(
+            "{}: Failed to install {} font families",
+            "error".red(),
+            failures.len()
+        );
+        for (fontname, err) in &failures {
+            eprintln!(
+                " {} {} {}",
+                "-".red(),
+                fontname,
+                format!("({})", err).dimmed()
+            );
+        }
+    }
+
+    if args.index_css {
+        match write_index_css(&output_dir, args.css_dir.as_deref()) {
+            Err(message) => GfontError::WriteFailed {
+                file: "index.css".to_string(),
+                message,
+            }
+            .report(args.json_errors),
+            Ok(file_path) => println!(
+                "{} {}",
+                "Finished writing index.css file to".dimmed(),
+                &file_path.dimmed()
+            ),
+        }
+    }
+
+    if args.include_preview_index {
+        match write_preview_index(&output_dir, args.css_dir.as_deref()) {
+            Err(message) => GfontError::WriteFailed {
+                file: "index.html".to_string(),
+                message,
+            }
+            .report(args.json_errors),
+            Ok(file_path) => println!(
+                "{} {}",
+                "Finished writing index.html file to".dimmed(),
+                &file_path.dimmed()
+            ),
+        }
+    }
+
+    if args.verbose {
+        print_quota();
+    }
+
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Matches the family file map or family name map against the fonts being installed, keyed by the
+/// lowercase font name. Every font must be mapped and every entry must name one of the fonts
+fn resolve_family_map<T>(
+    fontnames: &[String],
+    family_map: Vec<(String, T)>,
+    map_name: &str,
+) -> Result<HashMap<String, T>, String> {
+    if let Some((unknown, _)) = family_map.iter().find(|(mapped, _)| {
+        !fontnames
+            .iter()
+            .any(|fontname| fontname.eq_ignore_ascii_case(mapped))
+    }) {
+        return Err(format!(
+            "The {} has an entry for `{}`, which is not being installed",
+            map_name, unknown
+        ));
+    }
+
+    let mapped: HashMap<String, T> = family_map
+        .into_iter()
+        .map(|(fontname, value)| (fontname.to_lowercase(), value))
+        .collect();
+
+    let unmapped: Vec<&str> = fontnames
+        .iter()
+        .filter(|fontname| !mapped.contains_key(&fontname.to_lowercase()))
+        .map(String::as_str)
+        .collect();
+    if !unmapped.is_empty() {
+        return Err(format!(
+            "The {} has no entry for {}",
+            map_name,
+            unmapped.join(", ")
+        ));
+    }
+
+    Ok(mapped)
+}
+
+/// Downloads every font style of a font family to its directory in the output directory,
+/// then converts the fonts to woff2 and writes the stylesheet for the family
+async fn install_font_family(
+    client: &Client,
+    api: &ApiConfig,
+    fontname: &str,
+    mapped_family: Option<MappedFamily>,
+    output_dir: &Path,
+    args: &Args,
+    aliases: &FamilyAliases,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let font_family = fetch_font_data(
+        client,
+        api,
+        fontname,
+        if args.only_variable {
+            Some("VF")
+        } else {
+            args.emit_fontface_only.then_some("WOFF2")
+        },
+        args.family_index,
+        args.retries,
+        aliases,
+    )
+    .await?;
+    if args.family_case_sensitive && font_family.family != aliases.resolve(fontname) {
+        return Err(format!(
+            "Google fonts matched `{}` to {}, which is not an exact match",
+            aliases.resolve(fontname),
+            &font_family.family
+        )
+        .into());
+    }
+    if let Some(category) = &args.category {
+        if !font_family.category.eq_ignore_ascii_case(category) {
+            let message = format!(
+                "{} is in the {} category, not {}",
+                &font_family.family, &font_family.category, category
+            );
+            if args.strict_category {
+                return Err(message.into());
+            }
+            eprintln!("{}: {}", "warning".yellow(), message);
+        }
+    }
+    let weight_range = font_family.weight_range();
+    let (display_name, output_name) = match mapped_family {
+        Some(mapped) => (mapped.display_name, Some(mapped.output_name)),
+        None => (None, None),
+    };
+    let mut family_name =
+        output_name.unwrap_or_else(|| slugify(&font_family.family, args.slug_style));
+    if let Some(existing) = find_dir_differing_by_case(output_dir, &family_name) {
+        eprintln!(
+            "{}: Installing {} to the existing {} directory instead of {}, which only differs by case",
+            "warning".yellow(),
+            &font_family.family,
+            existing,
+            family_name
+        );
+        family_name = existing;
+    }
+    let font_dir = output_dir.join(&family_name);
+    let font_files = resolve_font_files(
+        font_family
+            .files
+            .iter()
+            .map(|(variant, url)| (variant.as_str(), url.as_str())),
+    )?;
+
+    if args.only_variable && font_files.variable_files.is_empty() {
+        return Err(format!(
+            "{} has no variable font files, --only-variable does not fall back to its {} static styles",
+            &font_family.family,
+            font_files.static_files.len()
+        )
+        .into());
+    }
+    if !font_files.variable_files.is_empty() && !args.only_variable {
+        eprintln!(
+            "{}: Skipping {} variable font files for {}",
+            "warning".yellow(),
+            font_files.variable_files.len(),
+            &family_name
+        );
+    }
+    let variable_files = font_files.variable_files;
+    let mut font_files = font_files.static_files;
+    let available_count = font_files.len();
+    if args.only_variable {
+        // The variable font files are installed as the regular and italic styles, covering every weight
+        font_files = vec![];
+        for (variant, url) in &variable_files {
+            let font_style = variable_font_style(variant);
+            if !font_files.iter().any(|(added, _)| *added == font_style) {
+                font_files.push((font_style, url.clone()));
+            }
+        }
+    }
+
+    if let Some(instance) = &args.instance {
+        let font_style =
+            find_instance_font_style(client, &font_family.family, &variable_files, instance)
+                .await?;
+        font_files.retain(|(available, _)| *available == font_style);
+    }
+
+    if let Some(weights_from_css) = &args.weights_from_css {
+        let css = std::fs::read_to_string(weights_from_css).map_err(|_| {
+            format!(
+                "Could not read stylesheet: {}",
+                weights_from_css.to_string_lossy()
+            )
+        })?;
+        let wanted_styles = parse_css_font_styles(&css, &font_family.family)?;
+
+        for font_style in &wanted_styles {
+            if !font_files
+                .iter()
+                .any(|(available, _)| available == font_style)
+            {
+                eprintln!(
+                    "{}: {} is not available for {}",
+                    "warning".yellow(),
+                    font_style,
+                    &font_family.family
+                );
+            }
+        }
+        font_files.retain(|(font_style, _)| wanted_styles.contains(font_style));
+    }
+
+    if !args.select.is_empty() {
+        for font_style in &args.select {
+            if !font_files
+                .iter()
+                .any(|(available, _)| available == font_style)
+            {
+                eprintln!(
+                    "{}: {} is not available for {}",
+                    "warning".yellow(),
+                    font_style,
+                    &font_family.family
+                );
+            }
+        }
+        font_files.retain(|(font_style, _)| args.select.contains(font_style));
+    }
+
+    let mut weight_fallbacks = BTreeMap::new();
+    let weights = args.weights()?;
+    let weight_aliases = args.weight_aliases()?;
+    if !weights.is_empty() {
+        let mut available_weights: Vec<u16> = font_files
+            .iter()
+            .map(|(font_style, _)| font_style.get_style_and_weight().1)
+            .collect();
+        available_weights.sort();
+        available_weights.dedup();
+
+        let mut wanted_weights = vec![];
+        let mut missing_weights = vec![];
+        for font_weight in weights {
+            if available_weights.contains(&font_weight) {
+                wanted_weights.push(font_weight);
+                continue;
+            }
+            if args.abort_on_missing_weight {
+                missing_weights.push(font_weight.to_string());
+                continue;
+            }
+
+            match (
+                args.weight_fallback,
+                nearest_weight(&available_weights, font_weight),
+            ) {
+                (WeightFallback::Nearest, Some(nearest)) => {
+                    eprintln!(
+                        "{}: {} is not available for {}, using {} instead",
+                        "warning".yellow(),
+                        font_weight,
+                        &font_family.family,
+                        nearest
+                    );
+                    wanted_weights.push(nearest);
+                    weight_fallbacks.insert(font_weight, nearest);
+                }
+                (WeightFallback::Strict, Some(nearest)) => eprintln!(
+                    "{}: {} is not available for {}, the nearest available weight is {} (pass --weight-fallback nearest to use it)",
+                    "warning".yellow(),
+                    font_weight,
+                    &font_family.family,
+                    nearest
+                ),
+                (_, None) => eprintln!(
+                    "{}: {} is not available for {}",
+                    "warning".yellow(),
+                    font_weight,
+                    &font_family.family
+                ),
+            }
+        }
+        if !missing_weights.is_empty() {
+            return Err(format!(
+                "{} has no weight {}, the available weights are {}",
+                &font_family.family,
+                missing_weights.join(", "),
+                available_weights
+                    .iter()
+                    .map(|font_weight| font_weight.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+            .into());
+        }
+        font_files.retain(|(font_style, _)| {
+            wanted_weights.contains(&font_style.get_style_and_weight().1)
+        });
+    }
+
+    if args.list_weights {
+        println!(
+            "{} has {} of {} font styles to download",
+            &font_family.family,
+            font_files.len().to_string().cyan(),
+            available_count
+        );
+        for (font_style, _) in &font_files {
+            println!(
+                " {} {}{}",
+                "+".green(),
+                &family_name,
+                format!("=={}", font_style.file_suffix(args.naming)).dimmed()
+            );
+        }
+        if !confirm(
+            &format!(
+                "Download {} font styles of {}?",
+                font_files.len(),
+                &font_family.family
+            ),
+            args.interaction(),
+        )? {
+            println!("{}", format!("Skipped {}", &font_family.family).dimmed());
+            return Ok(());
+        }
+    }
+
+    if args.font_format() == FontFormat::Otf {
+        if let Some((_, url)) = font_files
+            .iter()
+            .find(|(_, url)| url_extension(url) != Some("otf"))
+        {
+            return Err(format!(
+                "{} is not distributed as OpenType, google fonts serves {} files for it, which --format otf does not keep",
+                &font_family.family,
+                url_extension(url).unwrap_or("other")
+            )
+            .into());
+        }
+    }
+
+    let (download_files, css2_faces) = match (&args.subsets, &args.css2_text) {
+        (None, None) => (
+            font_files
+                .into_iter()
+                .map(|(font_style, url)| DownloadFile {
+                    font_style,
+                    url,
+                    subset: None,
+                })
+                .collect::<Vec<DownloadFile>>(),
+            None,
+        ),
+        (subsets, text) => {
+            let faces = fetch_subset_faces(
+                client,
+                &font_family,
+                &font_files,
+                subsets.as_deref(),
+                text.as_deref(),
+                args,
+            )
+            .await?;
+            let download_files = faces
+                .iter()
+                .map(|face| DownloadFile {
+                    font_style: face.font_style.clone(),
+                    url: face.url.clone(),
+                    subset: Some(face.subset.clone()),
+                })
+                .collect();
+            (download_files, Some(faces))
+        }
+    };
+
+    let mut css_options = CssOptions {
+        naming: args.naming,
+        fallback: args.fallback.clone().unwrap_or_else(|| {
+            if args.css_family_stack {
+                font_family.fallback_stack().to_string()
+            } else {
+                font_family.generic_fallback().to_string()
+            }
+        }),
+        base_url: args.css_base_url.clone(),
+        css_dir: args.css_dir.clone(),
+        relative_to: args.output_relative_to.clone(),
+        comment_per_face: args.css_comment_per_face,
+        weight_labels: weight_aliases
+            .iter()
+            .map(|(font_weight, label)| (*font_weight, label.clone()))
+            .chain(args.css_weight_override.iter().cloned())
+            .collect(),
+        file_weight_labels: weight_aliases.clone(),
+        order: args.order,
+        weight_range: weight_range.filter(|_| args.only_variable),
+        weight_fallbacks,
+        utility_classes: args.utility_classes,
+        family_stack: args.css_family_stack,
+        font_format: args.css_font_format(),
+        src_order: (!args.css_src_order.is_empty()).then(|| args.css_src_order.clone()),
+        quote: args.quote,
+        line_ending: args.line_ending,
+        skip_if_unchanged: args.skip_css_if_unchanged,
+        filename_style: args.filename_style,
+        layout: args.layout,
+        // Filled in from the downloaded files with --explicit-metrics
+        metrics: HashMap::new(),
+        display_name: Some(display_name.unwrap_or_else(|| {
+            format_font_string(&slugify(&font_family.family, SlugStyle::Kebab))
+        })),
+    };
+
+    if args.emit_fontface_only {
+        match &css_options.css_dir {
+            Some(css_dir) => std::fs::create_dir_all(css_dir)?,
+            None => std::fs::create_dir_all(&font_dir)?,
+        }
+        let remote_files: Vec<(FontStyles, String)> = download_files
+            .iter()
+            .map(|download_file| (download_file.font_style.clone(), download_file.url.clone()))
+            .collect();
+        let file_path =
+            write_remote_css_file_for_font(&remote_files, &font_dir, &family_name, &css_options)?;
+        println!(
+            "{} {}",
+            "Finished writing fonts.css file to".dimmed(),
+            &file_path.dimmed()
+        );
+        for (font_style, _) in &remote_files {
+            println!(
+                " {} {}{}",
+                "+".green(),
+                &family_name,
+                format!("=={}", font_style.file_suffix(args.naming)).dimmed()
+            );
+        }
+        return Ok(());
+    }
+
+    if args.print_url {
+        for download_file in &download_files {
+            let file_suffix = download_file.font_style.file_suffix(args.naming);
+            match &download_file.subset {
+                Some(subset) => println!(
+                    "{}=={}/{} {}",
+                    &family_name, file_suffix, subset, download_file.url
+                ),
+                None => println!("{}=={} {}", &family_name, file_suffix, download_file.url),
+            }
+        }
+        return Ok(());
+    }
+
+    if args.estimate {
+        let (total_size, unknown_count) = estimate_download_size(client, &download_files).await;
+        println!(
+            "{} {} {}",
+            "Estimated download size for".dimmed(),
+            &family_name.cyan(),
+            format!(
+                "{} ({} files)",
+                HumanBytes(total_size),
+                download_files.len()
+            )
+            .dimmed()
+        );
+        if unknown_count > 0 {
+            eprintln!(
+                "{}: Could not determine the size of {} files",
+                "warning".yellow(),
+                unknown_count
+            );
+        }
+        return Ok(());
+    }
+
+    println!(
+        "Creating font directory at: {}",
+        &font_dir.to_string_lossy().cyan()
+    );
+    ensure_dir_path(&font_dir)?;
+    std::fs::create_dir_all(&font_dir)?;
+
+    let download_options = DownloadOptions {
+        naming: args.naming,
+        jobs: args.jobs,
+        verify_woff2: args.verify_woff2,
+        verbose: args.verbose,
+        progress_chars: args.progress_style,
+        progress_color: args.progress_color,
+        family_progress: args.family_progress,
+        font_format: args.font_format(),
+        retries: args.retries,
+        convert_timeout: Duration::from_secs(args.convert_timeout),
+        strip_metadata: args.strip_metadata,
+        filename_style: args.filename_style,
+        layout: args.layout,
+        weight_labels: weight_aliases,
+        read_metrics: args.explicit_metrics || args.metrics_json,
+        store: if args.store {
+            Some(BlobStore::open()?)
+        } else {
+            None
+        },
+        locked_sources: match Lockfile::load(output_dir)?.families.remove(&family_name) {
+            Some(locked) if !args.update_lock => locked
+                .files
+                .into_iter()
+                .map(|(file_name, locked_file)| (file_name, locked_file.source_sha256))
+                .collect(),
+            _ => BTreeMap::new(),
+        },
+        json_errors: args.json_errors,
+    };
+    // Every file kept for the selected styles, so a style that fails to download keeps its previous file
+    let wanted_files: Vec<String> = download_files
+        .iter()
+        .flat_map(|download_file| {
+            let file_name = download_options.file_name(
+                &download_file.font_style,
+                download_file.subset.as_deref(),
+                &family_name,
+            );
+            let extensions = match download_file.subset {
+                Some(_) => FontFormat::Woff2.extensions(),
+                None => download_options.font_format.extensions(),
+            };
+            extensions.iter().map(move |extension| {
+                Path::new(&file_name)
+                    .with_extension(extension)
+                    .to_string_lossy()
+                    .to_string()
+            })
+        })
+        .collect();
+    let DownloadResults {
+        downloaded_files: download_results,
+        failed_files,
+        failed_conversions,
+        checksum_mismatches,
+        locked_files,
+        stripped_bytes,
+        metrics,
+    } = download_font_files(
+        client,
+        download_files,
+        &family_name,
+        &font_dir,
+        &download_options,
+    )
+    .await?;
+    // Nothing is written from a family whose files changed, so the stylesheet and lockfile keep describing the locked files
+    if checksum_mismatches > 0 {
+        return Err(format!(
+            "{} font files of {} do not match the checksums in {}, pass --update-lock to accept the new files",
+            checksum_mismatches, &font_family.family, LOCKFILE_NAME
+        )
+        .into());
+    }
+
+    if args.prune && (!failed_files.is_empty() || !failed_conversions.is_empty()) {
+        eprintln!(
+            "{}: Not pruning the font files of {} since some of its font files failed to install",
+            "warning".yellow(),
+            &font_family.family
+        );
+    } else if args.prune {
+        match prune_font_files(&wanted_files, &font_dir, args.interaction()) {
+            Err(message) => GfontError::PruneFailed {
+                family: font_family.family.clone(),
+                message,
+            }
+            .report(args.json_errors),
+            Ok(pruned_files) => {
+                for pruned_file in &pruned_files {
+                    println!(
+                        " {} {}",
+                        "-".red(),
+                        pruned_file.file_name().unwrap().to_string_lossy().dimmed()
+                    );
+                }
+            }
+        }
+    }
+
+    if args.metrics_json {
+        let file_path = write_metrics_json_for_font(
+            &download_results,
+            &font_dir,
+            &font_family.family,
+            &metrics,
+        )?;
+        println!(
+            "{} {}",
+            "Finished writing metrics.json file to".dimmed(),
+            &file_path.dimmed()
+        );
+    }
+    if args.explicit_metrics {
+        css_options.metrics = metrics
+            .into_iter()
+            .map(|(font_style, metrics)| (font_style, metrics.vertical))
+            .collect();
+    }
+
+    // --download-only leaves the ttf files as they are, without a stylesheet
+    if !args.download_only {
+        if let Some(css_dir) = &css_options.css_dir {
+            std::fs::create_dir_all(css_dir)?;
+        }
+        let css_file_name = args.output_format.file_name();
+        println!(
+            "{} {}",
+            format!("Writing {} file for", css_file_name).dimmed(),
+            &family_name.cyan()
+        );
+
+        let css_result = match (args.output_format, &css2_faces) {
+            (_, Some(faces)) => {
+                let downloaded_faces: Vec<Css2Face> = faces
+                    .iter()
+                    .filter(|face| {
+                        locked_files.contains_key(
+                            &face.file_name(args.filename_style.prefix(&family_name), args.naming),
+                        )
+                    })
+                    .cloned()
+                    .collect();
+                write_css2_file_for_font(&downloaded_faces, &font_dir, &family_name, &css_options)
+            }
+            (OutputFormat::Css, None) => {
+                write_css_file_for_font(&download_results, &font_dir, &family_name, &css_options)
+            }
+            (OutputFormat::CssModules, None) => {
+                write_css_module_for_font(&download_results, &font_dir, &family_name, &css_options)
+            }
+        };
+
+        match css_result {
+            Err(message) => GfontError::WriteFailed {
+                file: css_file_name.to_string(),
+                message,
+            }
+            .report(args.json_errors),
+            Ok(file_path) => {
+                println!(
+                    "{} {}",
+                    format!("Finished writing {} file to", css_file_name).dimmed(),
+                    &file_path.dimmed()
+                );
+                if args.validate_css {
+                    let css = std::fs::read_to_string(&file_path)
+                        .map_err(|_| format!("Could not read stylesheet: {}", file_path))?;
+                    validate_css(&css).map_err(|err| {
+                        format!("The generated {} is not valid css, {}", file_path, err)
+                    })?;
+                }
+                // Only written along with the css, so the two never disagree
+                if args.emit_json_and_css {
+                    match write_json_manifest_for_font(
+                        &download_results,
+                        &font_dir,
+                        &family_name,
+                        &css_options,
+                    ) {
+                        Err(message) => GfontError::WriteFailed {
+                            file: css_options
+                                .css_file_path(&font_dir, &family_name, "json")
+                                .file_name()
+                                .unwrap()
+                                .to_string_lossy()
+                                .to_string(),
+                            message,
+                        }
+                        .report(args.json_errors),
+                        Ok(file_path) => println!(
+                            "{} {}",
+                            "Finished writing manifest file to".dimmed(),
+                            &file_path.dimmed()
+                        ),
+                    }
+                }
+            }
+        }
+
+        if args.preview {
+            match write_preview_for_font(
+                &download_results,
+                &font_dir,
+                &family_name,
+                &css_options,
+                args.theme,
+            ) {
+                Err(message) => GfontError::WriteFailed {
+                    file: "preview.html".to_string(),
+                    message,
+                }
+                .report(args.json_errors),
+                Ok(file_path) => println!(
+                    "{} {}",
+                    "Finished writing preview file to".dimmed(),
+                    &file_path.dimmed()
+                ),
+            }
+        }
+    }
+
+    for font_style in &download_results {
+        println!(
+            " {} {}{}",
+            "+".green(),
+            &family_name,
+            format!("=={}", font_style.file_suffix(args.naming)).dimmed()
+        );
+    }
+    for (failed, failure) in [
+        (&failed_files, "download failed"),
+        (&failed_conversions, "conversion failed"),
+    ] {
+        for (font_style, subset) in failed {
+            let file_suffix = font_style.file_suffix(args.naming);
+            println!(
+                " {} {}{}",
+                "-".red(),
+                &family_name,
+                match subset {
+                    Some(subset) => format!("=={}/{} ({})", file_suffix, subset, failure),
+                    None => format!("=={} ({})", file_suffix, failure),
+                }
+                .dimmed()
+            );
+        }
+    }
+    if !failed_files.is_empty() {
+        eprintln!(
+            "{}: {} font files could not be downloaded, check your network connection",
+            "warning".yellow(),
+            failed_files.len()
+        );
+    }
+    if !failed_conversions.is_empty() {
+        eprintln!(
+            "{}: {} font files were downloaded but could not be converted, run {} to check woff2_compress",
+            "warning".yellow(),
+            failed_conversions.len(),
+            "gfontapi doctor".cyan()
+        );
+    }
+    if stripped_bytes > 0 {
+        println!(
+            "{} {}",
+            "Stripped metadata, saved".dimmed(),
+            HumanBytes(stripped_bytes).to_string().cyan()
+        );
+    }
+    if args.download_only {
+        let total_size: u64 = locked_files
+            .keys()
+            .filter_map(|file_name| std::fs::metadata(font_dir.join(file_name)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        println!(
+            "{} {} {}",
+            "Downloaded".dimmed(),
+            format!("{} files ({})", locked_files.len(), HumanBytes(total_size)).cyan(),
+            format!("to {}", font_dir.to_string_lossy()).dimmed()
+        );
+    }
 
-struct ProgressState {
-    downloaded_count: u16,
-    downloaded_files: Vec<FontStyles>,
+    let mut lockfile = Lockfile::load(output_dir)?;
+    let mut upstream = LockedFamily::from(&font_family);
+    if let Some(locked) = lockfile.families.get(&family_name) {
+        if let Some(drift) = locked.drift(&upstream) {
+            eprintln!("{}: {}", "warning".yellow(), drift);
+        }
+        // Files kept from previous installs stay locked until they are pruned
+        upstream.files = locked
+            .files
+            .iter()
+            .filter(|(file_name, _)| font_dir.join(file_name).is_file())
+            .map(|(file_name, locked_file)| (file_name.clone(), locked_file.clone()))
+            .collect();
+    }
+    upstream.files.extend(locked_files);
+    lockfile.families.insert(family_name, upstream);
+    lockfile.save(output_dir)?;
+
+    Ok(())
 }
 
-// TODO: Separate into commands := add, remove, compress (some people might prefer ttf idk)
-// TODO: add, remove := specific weights, styles
-// TODO: Add colors to CLI output
-#[derive(Parser)]
-#[command(name = "gfontapi")]
-#[command(version = "0.1.0")]
-#[command(about = "Manage all your google fonts from the terminal.")]
-#[command(
-    help_template = "{about}\n\nUsage: {name} [OPTIONS] \"[fontname]\"\n\nOptions\n{options}"
-)]
-struct Args {
-    /// Name of the font to download
-    #[arg(value_name = "fontname")]
-    fontname: String,
-    /// Directory to place the converted fonts
-    #[arg(
-        short,
-        long = "target-dir",
-        help_heading = "options",
-        help = "target directory, defaults to ./fonts."
-    )]
-    target_dir: Option<PathBuf>,
-    /// Users google application API key
-    #[arg(
-        short,
-        long = "api-key",
-        help_heading = "options",
-        help = "google api key generated from developer console, can also be set as `EXPORT GFONT_API_KEY=<API_KEY>`"
-    )]
-    api_key: Option<String>,
+/// Fetches the `@font-face` rules of the selected subsets of the font styles from the css2 API.
+/// `--subsets all` also selects the numbered slices large fonts, e.g. CJK fonts, are split into.
+/// With `--css2-text`, the single face of each font style containing the glyphs of the text is fetched instead
+async fn fetch_subset_faces(
+    client: &Client,
+    font_family: &FontFamily,
+    font_files: &[(FontStyles, String)],
+    subsets: Option<&[String]>,
+    text: Option<&str>,
+    args: &Args,
+) -> Result<Vec<Css2Face>, Box<dyn std::error::Error>> {
+    let subsets = subsets.unwrap_or_default();
+    let select_all = text.is_some() || subsets.iter().any(|subset| subset == "all");
+    let subsets = resolve_subsets(subsets, &font_family.subsets)?;
+    if font_files.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let font_styles: Vec<FontStyles> = font_files
+        .iter()
+        .map(|(font_style, _)| font_style.clone())
+        .collect();
+    let faces = fetch_css2_faces(
+        client,
+        &font_family.family,
+        &font_styles,
+        text,
+        args.retries,
+    )
+    .await?;
+
+    Ok(faces
+        .into_iter()
+        .filter(|face| select_all || subsets.contains(&face.subset))
+        .collect())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// Finds the static font style matching a named instance of a font family's variable fonts
+async fn find_instance_font_style(
+    client: &Client,
+    font_family: &str,
+    variable_files: &[(String, String)],
+    instance: &str,
+) -> Result<FontStyles, Box<dyn std::error::Error>> {
+    if variable_files.is_empty() {
+        return Err(format!(
+            "{} has no variable font files to pick an instance from",
+            font_family
+        )
+        .into());
+    }
 
-    let output_dir = get_output_dir(args.target_dir);
-    let api_key = get_api_key(args.api_key);
-    let client = reqwest::Client::builder().build()?;
+    let mut instance_names = vec![];
+    for (_, url) in variable_files {
+        for named_instance in fetch_named_instances(client, url).await? {
+            if named_instance.name.eq_ignore_ascii_case(instance) {
+                return font_style_for_instance(&named_instance).ok_or_else(|| {
+                    format!("No static font matches the `{}` instance", instance).into()
+                });
+            }
+            instance_names.push(named_instance.name);
+        }
+    }
 
-    let font_family = fetch_font_data(&client, &api_key, &args.fontname).await?;
-    let family_name = font_family.family.to_lowercase().replace(' ', "-");
-    let font_dir = output_dir.join(&family_name);
+    Err(format!(
+        "{} has no named instance `{}`, available instances: {}",
+        font_family,
+        instance,
+        instance_names.join(", ")
+    )
+    .into())
+}
+
+/// Compares the upstream version of a font family with the one recorded in the lockfile.
+/// Returns whether the font family is unchanged
+async fn check_font_family(
+    client: &Client,
+    api: &ApiConfig,
+    fontname: &str,
+    output_name: Option<String>,
+    lockfile: &Lockfile,
+    args: &Args,
+    aliases: &FamilyAliases,
+) -> bool {
+    let font_family = match fetch_font_data(
+        client,
+        api,
+        fontname,
+        None,
+        args.family_index,
+        args.retries,
+        aliases,
+    )
+    .await
+    {
+        Ok(font_family) => font_family,
+        Err(err) => {
+            GfontError::CheckFailed {
+                family: fontname.to_string(),
+                message: err.to_string(),
+            }
+            .report(args.json_errors);
+            return false;
+        }
+    };
+    let family_name = output_name.unwrap_or_else(|| slugify(&font_family.family, args.slug_style));
+    let upstream = LockedFamily::from(&font_family);
+
+    match lockfile.families.get(&family_name) {
+        None => {
+            eprintln!(
+                "{}: {} is not locked in {}",
+                "warning".yellow(),
+                &font_family.family,
+                LOCKFILE_NAME
+            );
+            false
+        }
+        Some(locked) => match locked.drift(&upstream) {
+            Some(drift) => {
+                eprintln!("{}: {}", "warning".yellow(), drift);
+                false
+            }
+            None => {
+                println!(
+                    " {} {} {}",
+                    "=".green(),
+                    &family_name,
+                    format!(
+                        "{} ({})",
+                        upstream.version.as_deref().unwrap_or("unknown version"),
+                        upstream.last_modified.as_deref().unwrap_or("unknown date")
+                    )
+                    .dimmed()
+                );
+                true
+            }
+        },
+    }
+}
+
+async fn run_command(
+    client: &Client,
+    args: &Args,
+    aliases: &FamilyAliases,
+    output_dir: &Path,
+    command: Command,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let retries = args.retries;
+    match command {
+        Command::List {
+            regex,
+            category,
+            catalog_args,
+        } => {
+            let catalog = args.catalog(client, &catalog_args).await?;
+            list_families(&catalog, regex.as_ref(), category.as_deref())?;
+        }
+        Command::Search {
+            query,
+            catalog_args,
+        } => {
+            let catalog = args.catalog(client, &catalog_args).await?;
+            search_families(&catalog, &query)?;
+        }
+        Command::Random { catalog_args } => {
+            let catalog = args.catalog(client, &catalog_args).await?;
+            random_family(&catalog)?;
+        }
+        Command::Prefetch => {
+            let api = args.api()?;
+            if args.no_cache {
+                return Err("prefetch caches the catalog, which --no-cache turns off".into());
+            }
+            let catalog = load_catalog(client, &api, CatalogCache::Refresh, retries).await?;
+            print_cached_catalog(&catalog);
+        }
+        Command::Info {
+            fontname,
+            catalog_file,
+        } => {
+            let font_family = match catalog_file {
+                Some(catalog_file) => {
+                    let font_name = aliases.resolve(&fontname);
+                    read_catalog_file(&catalog_file)?
+                        .families
+                        .into_iter()
+                        .find(|font_family| font_family.family.eq_ignore_ascii_case(font_name))
+                        .ok_or_else(|| {
+                            format!(
+                                "No font family named `{}` in {}",
+                                font_name,
+                                catalog_file.to_string_lossy()
+                            )
+                        })?
+                }
+                None => {
+                    fetch_font_data(client, &args.api()?, &fontname, None, 0, retries, aliases)
+                        .await?
+                }
+            };
+            print_font_info(client, &font_family).await?;
+        }
+        Command::Installed { json } => {
+            let installed_families = find_installed_families(output_dir)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&installed_families)?);
+            } else if installed_families.is_empty() {
+                return Err(GfontError::NothingInstalled {
+                    dir: output_dir.to_string_lossy().to_string(),
+                }
+                .into());
+            } else {
+                print_installed_families(&installed_families);
+            }
+        }
+        Command::Verify => {
+            if !verify_font_files(output_dir)? {
+                process::exit(1);
+            }
+        }
+        Command::Doctor { fix } => {
+            let api_key_set = args.api_key.is_some()
+                || env::var("GFONT_API_KEY").is_ok_and(|api_key| !api_key.is_empty());
+            if !run_doctor(api_key_set, fix, args.interaction())? {
+                process::exit(1);
+            }
+        }
+        Command::Probe => {
+            if !run_probe(client, &args.api()?).await? {
+                process::exit(1);
+            }
+        }
+        Command::Export { bundle, family } => {
+            let family_name = match family {
+                Some(family) => family,
+                None => bundle
+                    .file_stem()
+                    .map(|file_stem| file_stem.to_string_lossy().to_string())
+                    .ok_or("The bundle has no name")?,
+            };
+            let file_names = export_bundle(output_dir, &family_name, &bundle)?;
+            println!(
+                "{} {} {}",
+                "Exported".dimmed(),
+                family_name.cyan(),
+                format!(
+                    "({} files) to {}",
+                    file_names.len(),
+                    bundle.to_string_lossy()
+                )
+                .dimmed()
+            );
+        }
+        Command::Import { bundle } => {
+            let (font_dir, file_names) = import_bundle(output_dir, &bundle)?;
+            for file_name in &file_names {
+                println!(" {} {}", "+".green(), file_name.dimmed());
+            }
+            // Bundles of fonts whose stylesheets were written elsewhere get a fresh fonts.css
+            if !file_names
+                .iter()
+                .any(|file_name| file_name.starts_with("fonts."))
+            {
+                regenerate_css(&font_dir)?;
+            }
+            println!(
+                "{} {}",
+                "Imported bundle to".dimmed(),
+                font_dir.to_string_lossy().cyan()
+            );
+        }
+        Command::Css { font_dir } => regenerate_css(&font_dir)?,
+    }
+
+    Ok(())
+}
+
+/// Re-hashes the installed font files of every font family in the lockfile and reports
+/// files that are missing or whose contents changed. Returns whether every file matched
+fn verify_font_files(output_dir: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let lockfile = Lockfile::load(output_dir)?;
+    if lockfile.families.is_empty() {
+        return Err(format!(
+            "No font families are locked in {}",
+            output_dir.join(LOCKFILE_NAME).to_string_lossy()
+        )
+        .into());
+    }
+
+    let mut all_matched = true;
+    for (family_name, locked) in &lockfile.families {
+        if locked.files.is_empty() {
+            eprintln!(
+                "{}: No checksums recorded for {}, install it again to record them",
+                "warning".yellow(),
+                family_name
+            );
+            continue;
+        }
 
+        let font_dir = output_dir.join(family_name);
+        let mut family_matched = true;
+        for (file_name, checksum) in &locked.files {
+            let problem = match sha256_file(&font_dir.join(file_name)) {
+                Err(_) => Some("missing"),
+                Ok(sha256) if sha256 != checksum.sha256 => Some("checksum mismatch"),
+                Ok(_) => None,
+            };
+            if let Some(problem) = problem {
+                eprintln!(
+                    " {} {} {}",
+                    "!".red(),
+                    font_dir.join(file_name).to_string_lossy(),
+                    format!("({})", problem).dimmed()
+                );
+                family_matched = false;
+            }
+        }
+
+        if family_matched {
+            println!(
+                " {} {} {}",
+                "=".green(),
+                family_name,
+                format!("({} files)", locked.files.len()).dimmed()
+            );
+        }
+        all_matched &= family_matched;
+    }
+
+    Ok(all_matched)
+}
+
+/// Prints the details of a font family and the named instances of its variable font files
+async fn print_font_info(
+    client: &Client,
+    font_family: &FontFamily,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let font_files = resolve_font_files(
+        font_family
+            .files
+            .iter()
+            .map(|(variant, url)| (variant.as_str(), url.as_str())),
+    )?;
+
+    println!("{}", &font_family.family.cyan());
+    println!(" {} {}", "category:".dimmed(), &font_family.category);
+    if let Some(version) = &font_family.version {
+        println!(" {} {}", "version:".dimmed(), version);
+    }
+    if let Some(last_modified) = &font_family.last_modified {
+        println!(" {} {}", "last modified:".dimmed(), last_modified);
+    }
     println!(
-        "Creating font directory at: {}",
-        &font_dir.to_string_lossy().cyan()
+        " {} {}",
+        "subsets:".dimmed(),
+        font_family.subsets.join(", ")
     );
-    std::fs::create_dir_all(&font_dir)?;
+    // A catalog file without the `files` of each family only has the variant names
+    let styles = if font_family.files.is_empty() {
+        font_family.variants.clone()
+    } else {
+        font_files
+            .static_files
+            .iter()
+            .map(|(font_style, _)| font_style.to_string())
+            .collect()
+    };
+    println!(" {} {}", "styles:".dimmed(), styles.join(", "));
+
+    for (variant, url) in &font_files.variable_files {
+        let named_instances = fetch_named_instances(client, url).await?;
+        println!(
+            " {} {}",
+            format!("named instances ({}):", variant).dimmed(),
+            named_instances
+                .iter()
+                .map(|named_instance| named_instance.name.clone())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+
+    Ok(())
+}
 
-    let download_results =
-        download_font_files(&client, &font_family, &family_name, &font_dir).await?;
+/// Name of the font family a font directory belongs to, the name of the directory, e.g. `inter` for `./fonts/inter/`
+fn font_dir_family_name(font_dir: &Path) -> Result<String, String> {
+    font_dir
+        .canonicalize()
+        .map_err(|_| {
+            format!(
+                "Could not find font directory: {}",
+                font_dir.to_string_lossy()
+            )
+        })?
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .ok_or_else(|| "The font directory has no name".to_string())
+}
+
+/// Regenerates the `fonts.css` file of a font directory from the woff2 files in it, without any network access
+fn regenerate_css(font_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let family_name = font_dir_family_name(font_dir)?;
 
+    let (font_styles, naming, filename_style) = scan_font_styles(font_dir, &family_name, "woff2")?;
+    if font_styles.is_empty() {
+        return Err(format!(
+            "No woff2 files for {} found in {}",
+            family_name,
+            font_dir.to_string_lossy()
+        )
+        .into());
+    }
+
+    let file_path = write_css_file_for_font(
+        &font_styles,
+        font_dir,
+        &family_name,
+        &CssOptions {
+            naming,
+            filename_style,
+            ..Default::default()
+        },
+    )?;
     println!(
         "{} {}",
-        "Writing fonts.css file for".dimmed(),
-        &family_name.cyan()
+        "Finished writing fonts.css file to".dimmed(),
+        &file_path.dimmed()
     );
 
-    match write_css_file_for_font(&download_results, &font_dir, &family_name) {
-        Err(err) => eprintln!(
-            "{}: Failed to write fonts file\n  {}: {}",
-            "error".red(),
-            "Caused by".red(),
-            err
-        ),
-        Ok(file_path) => println!(
-            "{} {}",
-            "Finished writing fonts.css file to".dimmed(),
-            &file_path.dimmed()
-        ),
-    }
-
-    for font_style in &download_results {
+    for font_style in &font_styles {
         println!(
             " {} {}{}",
             "+".green(),
             &family_name,
-            format!("=={}", &font_style).dimmed()
+            format!("=={}", font_style.file_suffix(naming)).dimmed()
         );
     }
 
     Ok(())
 }
 
+/// Converts the `{family}-{style}.ttf` files of a font directory to woff2 files next to them and writes
+/// the `fonts.css` file for the converted files, without any network access. The ttf files are kept
+fn compress_ttf_dir(
+    ttf_dir: &Path,
+    convert_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let family_name = font_dir_family_name(ttf_dir)?;
+
+    let (font_styles, naming, filename_style) = scan_font_styles(ttf_dir, &family_name, "ttf")?;
+    if font_styles.is_empty() {
+        return Err(format!(
+            "No ttf files for {} found in {}",
+            family_name,
+            ttf_dir.to_string_lossy()
+        )
+        .into());
+    }
+
+    println!(
+        "{} {}",
+        "Converting ttf files for".dimmed(),
+        &family_name.cyan()
+    );
+    let mut converted_styles = vec![];
+    for font_style in font_styles {
+        let ttf_path =
+            ttf_dir.join(font_style.file_name(filename_style.prefix(&family_name), naming, "ttf"));
+        match convert_to_woff2(&ttf_path, true, convert_timeout) {
+            Ok(()) => {
+                println!(
+                    " {} {}{}",
+                    "+".green(),
+                    &family_name,
+                    format!("=={}", font_style.file_suffix(naming)).dimmed()
+                );
+                converted_styles.push(font_style);
+            }
+            Err(err) => println!(
+                " {} {}{} {}",
+                "-".red(),
+                &family_name,
+                format!("=={} (failed)", font_style.file_suffix(naming)).dimmed(),
+                err.dimmed()
+            ),
+        }
+    }
+    if converted_styles.is_empty() {
+        return Err(format!("Could not convert any ttf file for {}", family_name).into());
+    }
+
+    let file_path = write_css_file_for_font(
+        &converted_styles,
+        ttf_dir,
+        &family_name,
+        &CssOptions {
+            naming,
+            filename_style,
+            ..Default::default()
+        },
+    )?;
+    println!(
+        "{} {}",
+        "Finished writing fonts.css file to".dimmed(),
+        &file_path.dimmed()
+    );
+
+    Ok(())
+}
+
 async fn download_font_files(
     client: &Client,
-    font_family: &FontFamily,
+    font_files: Vec<DownloadFile>,
     family_name: &str,
-    output_dir: &PathBuf,
-) -> Result<Vec<FontStyles>, Box<dyn std::error::Error>> {
+    output_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<DownloadResults, Box<dyn std::error::Error>> {
     let start_time = Instant::now();
-    let total_files = font_family.files.len();
+    let total_files = font_files.len();
     let progress_state = Arc::new(Mutex::new(ProgressState {
         downloaded_count: 0,
         downloaded_files: vec![],
+        locked_files: BTreeMap::new(),
+        stripped_bytes: 0,
+        metrics: HashMap::new(),
     }));
 
     let spinner = ProgressBar::new_spinner();
 
     let progress_style = ProgressStyle::with_template("{spinner:.white} {msg}")
         .unwrap()
-        .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏");
+        .tick_chars(options.progress_chars.tick_chars());
 
     let completion_style = ProgressStyle::with_template("{msg.dimmed()}").unwrap();
 
@@ -133,61 +2924,314 @@ async fn download_font_files(
 
     let mp = Arc::new(MultiProgress::new());
     let spinner = mp.add(spinner);
+    let family_bar = options.family_progress.then(|| {
+        let family_bar = mp.add(ProgressBar::new(total_files as u64));
+        family_bar.set_style(
+            ProgressStyle::with_template(&format!(
+                "{{msg:10.dim}} {{bar:30.{}/dim}} {{pos}}/{{len}} files",
+                options
+                    .progress_color
+                    .to_possible_value()
+                    .unwrap()
+                    .get_name()
+            ))
+            .unwrap()
+            .progress_chars(options.progress_chars.progress_chars()),
+        );
+        family_bar.set_message(family_name.to_string());
+        family_bar
+    });
 
-    let mut download_tasks = FuturesUnordered::new();
+    let (download_limit, convert_limit) = options.jobs.limits();
+    let download_semaphore = Arc::new(Semaphore::new(download_limit));
+    let convert_semaphore = Arc::new(Semaphore::new(convert_limit));
+    // The files of a family recorded in the lockfile are downloaded and converted in a staging directory,
+    // and only moved into place once every file of the family matches the lockfile
+    let staging_dir = (!options.locked_sources.is_empty()).then(|| output_dir.join(".download"));
 
-    for (variant, url) in &font_family.files {
-        let font_style = transpile_font_weight(variant)
-            .map_err(|e| format!("Couldn't find variant mapping for {}: {}", variant, e))?;
+    let mut download_tasks = FuturesUnordered::new();
 
-        let download_url = url.to_string();
+    for download_file in font_files {
+        let font_style = download_file.font_style;
         let progress_state_clone = Arc::clone(&progress_state);
         let spinner_clone = spinner.clone();
         let mp_clone = Arc::clone(&mp);
         let family_name_str = family_name.to_string();
         let client_clone = client.clone();
-        let output_path = output_dir.join(format!("{}-{}.ttf", family_name, font_style));
+        let options_clone = options.clone();
+        let download_semaphore_clone = Arc::clone(&download_semaphore);
+        let convert_semaphore_clone = Arc::clone(&convert_semaphore);
+        let file_name =
+            options.file_name(&font_style, download_file.subset.as_deref(), family_name);
+        let output_path =
+            staging_dir
+                .as_deref()
+                .unwrap_or(output_dir)
+                .join(match &download_file.subset {
+                    Some(_) => file_name,
+                    None => options.layout.file_path(&font_style, &file_name),
+                });
+        let subset = download_file.subset;
+        let file_id = (font_style.clone(), subset.clone());
 
         let task = tokio::spawn(async move {
-            let pb = mp_clone.add(ProgressBar::new(100));
-            pb.set_style(
-                ProgressStyle::with_template("{msg:10.dim} {bar:30.green/dim}")
+            let result = async move {
+                let download_permit = download_semaphore_clone.acquire_owned().await.unwrap();
+                // With --family-progress the files only count towards the bar of the family
+                let pb = if options_clone.family_progress {
+                    ProgressBar::hidden()
+                } else {
+                    mp_clone.add(ProgressBar::new(100))
+                };
+                pb.set_style(
+                    ProgressStyle::with_template(&format!(
+                        "{{msg:10.dim}} {{bar:30.{}/dim}}",
+                        options_clone
+                            .progress_color
+                            .to_possible_value()
+                            .unwrap()
+                            .get_name()
+                    ))
                     .unwrap()
-                    .progress_chars("--"),
-            );
-            pb.set_message(format!("{}=={}", family_name_str, font_style.dimmed()));
-            let result =
-                download_font_file(&client_clone, &download_url, &output_path, pb.clone()).await;
-            pb.finish_and_clear();
-            convert_to_woff2(&output_path)?;
+                    .progress_chars(options_clone.progress_chars.progress_chars()),
+                );
+                pb.set_message(format!(
+                    "{}=={}",
+                    family_name_str,
+                    match &subset {
+                        Some(subset) => format!(
+                            "{}/{}",
+                            font_style.file_suffix(options_clone.naming),
+                            subset
+                        ),
+                        None => font_style.file_suffix(options_clone.naming),
+                    }
+                    .dimmed()
+                ));
+                let locked_source = ["woff2", "ttf", "otf"].iter().find_map(|extension| {
+                    options_clone.locked_sources.get(
+                        &options_clone.layout.file_path(
+                            &font_style,
+                            &output_path
+                                .with_extension(extension)
+                                .file_name()
+                                .unwrap()
+                                .to_string_lossy(),
+                        ),
+                    )
+                });
+                let result = download_font_file(
+                    &client_clone,
+                    &download_file.url,
+                    &output_path,
+                    options_clone.retries,
+                    options_clone.store.as_ref(),
+                    pb.clone(),
+                )
+                .await;
+                pb.finish_and_clear();
+                drop(download_permit);
+                let (final_url, source_sha256, from_store) = result?;
+                if let Some(locked_source) = locked_source {
+                    if *locked_source != source_sha256 {
+                        return Err(FileError::ChecksumMismatch(format!(
+                            "{} from {} does not match {}, expected sha256 {} but downloaded {}",
+                            output_path.file_name().unwrap().to_string_lossy(),
+                            final_url,
+                            LOCKFILE_NAME,
+                            locked_source,
+                            source_sha256
+                        )));
+                    }
+                }
+                if options_clone.verbose && from_store {
+                    mp_clone
+                        .println(format!(
+                            "{} {} {}",
+                            "Copied".dimmed(),
+                            output_path.file_name().unwrap().to_string_lossy(),
+                            "from the store".dimmed()
+                        ))
+                        .ok();
+                } else if options_clone.verbose {
+                    mp_clone
+                        .println(format!(
+                            "{} {} {}",
+                            "Downloaded".dimmed(),
+                            output_path.file_name().unwrap().to_string_lossy(),
+                            format!("from {}", final_url).dimmed()
+                        ))
+                        .ok();
+                }
 
-            let mut progress_state = progress_state_clone.lock().unwrap();
-            progress_state.downloaded_count += 1;
-            if result.is_ok() {
-                progress_state.downloaded_files.push(font_style);
-            }
+                let convert_permit = convert_semaphore_clone.acquire_owned().await.unwrap();
+                let stripped_bytes = if options_clone.strip_metadata && subset.is_none() {
+                    strip_font_file(&output_path)?
+                } else {
+                    0
+                };
+                let metrics = if options_clone.read_metrics && subset.is_none() {
+                    Some(read_font_metrics(&output_path).map_err(FileError::ConversionFailed)?)
+                } else {
+                    None
+                };
+                // Subset files from the css2 API are already woff2 files
+                let font_format = match subset {
+                    Some(_) => FontFormat::Woff2,
+                    None => options_clone.font_format,
+                };
+                if subset.is_none() && font_format.has_woff2() {
+                    convert_to_woff2(
+                        &output_path,
+                        font_format == FontFormat::Both,
+                        options_clone.convert_timeout,
+                    )
+                    .map_err(FileError::ConversionFailed)?;
+                }
+                if options_clone.verify_woff2 && font_format.has_woff2() {
+                    verify_woff2(&output_path.with_extension("woff2"))
+                        .map_err(FileError::ConversionFailed)?;
+                }
+                let mut locked_files = vec![];
+                for extension in font_format.extensions() {
+                    let file_path = output_path.with_extension(extension);
+                    locked_files.push((
+                        options_clone.layout.file_path(
+                            &font_style,
+                            &file_path.file_name().unwrap().to_string_lossy(),
+                        ),
+                        LockedFile {
+                            url: Some(final_url.clone()),
+                            source_sha256: source_sha256.clone(),
+                            sha256: sha256_file(&file_path)?,
+                        },
+                    ));
+                }
+                drop(convert_permit);
+
+                let mut progress_state = progress_state_clone.lock().unwrap();
+                progress_state.downloaded_count += 1;
+                progress_state.locked_files.extend(locked_files);
+                progress_state.stripped_bytes += stripped_bytes;
+                if let Some(metrics) = metrics {
+                    progress_state.metrics.insert(font_style.clone(), metrics);
+                }
+                if !progress_state.downloaded_files.contains(&font_style) {
+                    progress_state.downloaded_files.push(font_style);
+                }
 
-            // Update the spinner message with the current progress
-            spinner_clone.set_message(format!(
-                "Converting fonts... ({}/{})",
-                progress_state.downloaded_count, total_files
-            ));
+                // Update the spinner message with the current progress
+                spinner_clone.set_message(format!(
+                    "Converting fonts... ({}/{})",
+                    progress_state.downloaded_count, total_files
+                ));
 
-            result
+                Ok::<(), FileError>(())
+            }
+            .await;
+            (file_id, result)
         });
 
         download_tasks.push(task);
     }
 
+    let mut disk_full = None;
+    let mut failed_files = vec![];
+    let mut failed_conversions = vec![];
+    let mut checksum_mismatches = 0;
     while let Some(result) = download_tasks.next().await {
-        if let Err(e) = result {
-            eprintln!("Task error: {}", e);
-        } else if let Ok(Err(e)) = result {
-            eprintln!("Download error: {}", e);
+        if let Some(family_bar) = &family_bar {
+            family_bar.inc(1);
+        }
+        // Style of the failed file, e.g. `bold` or `bold/latin` for a subset
+        let file_style = |(font_style, subset): &(FontStyles, Option<String>)| match subset {
+            Some(subset) => format!("{}/{}", font_style.file_suffix(options.naming), subset),
+            None => font_style.file_suffix(options.naming),
+        };
+        match result {
+            Err(e) if e.is_cancelled() => {}
+            Err(e) => mp.suspend(|| {
+                GfontError::Failed {
+                    command: "install".to_string(),
+                    message: format!("Task error: {}", e),
+                }
+                .report(options.json_errors)
+            }),
+            Ok((_, Err(FileError::DiskFull(message)))) => {
+                // Nothing else can be written either, so stop the remaining downloads
+                for task in download_tasks.iter() {
+                    task.abort();
+                }
+                disk_full.get_or_insert(message);
+            }
+            Ok((file_id, Err(FileError::Failed(message)))) => {
+                mp.suspend(|| {
+                    GfontError::DownloadFailed {
+                        family: family_name.to_string(),
+                        style: file_style(&file_id),
+                        message,
+                    }
+                    .report(options.json_errors)
+                });
+                failed_files.push(file_id);
+            }
+            Ok((file_id, Err(FileError::ConversionFailed(message)))) => {
+                mp.suspend(|| {
+                    GfontError::ConversionFailed {
+                        family: family_name.to_string(),
+                        style: file_style(&file_id),
+                        message,
+                    }
+                    .report(options.json_errors)
+                });
+                failed_conversions.push(file_id);
+            }
+            Ok((file_id, Err(FileError::ChecksumMismatch(message)))) => {
+                mp.suspend(|| {
+                    GfontError::ChecksumMismatch {
+                        family: family_name.to_string(),
+                        style: file_style(&file_id),
+                        message,
+                    }
+                    .report(options.json_errors)
+                });
+                checksum_mismatches += 1;
+            }
+            Ok((_, Ok(()))) => {}
+        }
+    }
+    if let Some(family_bar) = family_bar {
+        family_bar.finish_and_clear();
+    }
+    if let Some(staging_dir) = &staging_dir {
+        // Nothing is installed from a family whose files changed, and the staged files are removed either way
+        let installed = match disk_full {
+            None if checksum_mismatches == 0 => {
+                let locked_files = progress_state.lock().unwrap().locked_files.clone();
+                install_staged_files(staging_dir, output_dir, locked_files.keys())
+            }
+            _ => Ok(()),
+        };
+        std::fs::remove_dir_all(staging_dir).ok();
+        if let Err(message) = installed {
+            spinner.finish_and_clear();
+            return Err(message.into());
         }
     }
+    if let Some(message) = disk_full {
+        spinner.finish_and_clear();
+        return Err(message.into());
+    }
 
-    let downloaded_files = progress_state.lock().unwrap().downloaded_files.clone();
+    let (downloaded_files, locked_files, stripped_bytes, metrics) = {
+        let progress_state = progress_state.lock().unwrap();
+        (
+            progress_state.downloaded_files.clone(),
+            progress_state.locked_files.clone(),
+            progress_state.stripped_bytes,
+            progress_state.metrics.clone(),
+        )
+    };
     let download_count = downloaded_files.len();
 
     let duration = start_time.elapsed();
@@ -202,48 +3246,249 @@ async fn download_font_files(
 
     spinner.finish();
 
-    Ok(downloaded_files)
+    Ok(DownloadResults {
+        downloaded_files,
+        failed_files,
+        failed_conversions,
+        checksum_mismatches,
+        locked_files,
+        stripped_bytes,
+        metrics,
+    })
+}
+
+/// Moves the files of a font family out of its staging directory into the font directory
+fn install_staged_files<'a>(
+    staging_dir: &Path,
+    output_dir: &Path,
+    file_names: impl Iterator<Item = &'a String>,
+) -> Result<(), String> {
+    for file_name in file_names {
+        let output_path = output_dir.join(file_name);
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|_| format!("Could not create directory: {}", parent.to_string_lossy()))?;
+        }
+        std::fs::rename(staging_dir.join(file_name), &output_path)
+            .map_err(|_| format!("Could not create file at path: {:?}", output_path))?;
+    }
+
+    Ok(())
+}
+
+/// Strips the metadata of a downloaded ttf file in place. Returns the number of bytes removed
+fn strip_font_file(path: &Path) -> Result<u64, FileError> {
+    let data = std::fs::read(path).map_err(|_| {
+        FileError::ConversionFailed(format!("Could not read file: {}", path.to_string_lossy()))
+    })?;
+    let stripped = strip_metadata(&data).map_err(|err| {
+        FileError::ConversionFailed(format!(
+            "Could not strip {}: {}",
+            path.to_string_lossy(),
+            err
+        ))
+    })?;
+    std::fs::write(path, &stripped).map_err(|err| {
+        if is_disk_full(&err) {
+            std::fs::remove_file(path).ok();
+            return FileError::DiskFull(disk_full_message(path));
+        }
+        FileError::ConversionFailed(format!("Could not create file at path: {:?}", path))
+    })?;
+
+    Ok(data.len().saturating_sub(stripped.len()) as u64)
+}
+
+/// Reads the metrics of a downloaded ttf file
+fn read_font_metrics(path: &Path) -> Result<FontMetrics, String> {
+    let data = std::fs::read(path)
+        .map_err(|_| format!("Could not read file: {}", path.to_string_lossy()))?;
+    Sfnt::parse(&data)
+        .and_then(|sfnt| sfnt.font_metrics())
+        .map_err(|err| {
+            format!(
+                "Could not read the metrics of {}: {}",
+                path.to_string_lossy(),
+                err
+            )
+        })
+}
+
+/// Sums the `content-length` of each font file using parallel HEAD requests.
+/// Returns the total size in bytes and the number of files whose size could not be determined
+async fn estimate_download_size(client: &Client, font_files: &[DownloadFile]) -> (u64, usize) {
+    let mut head_requests = font_files
+        .iter()
+        .map(|font_file| async move {
+            let started = Instant::now();
+            let response = client.head(&font_file.url).send().await;
+            record_request(
+                "HEAD",
+                &font_file.url,
+                response.as_ref().ok().map(|response| response.status()),
+                0,
+                started.elapsed(),
+            );
+            response
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut total_size: u64 = 0;
+    let mut unknown_count = 0;
+
+    while let Some(response) = head_requests.next().await {
+        match response.ok().and_then(|response| {
+            response
+                .headers()
+                .get(CONTENT_LENGTH)?
+                .to_str()
+                .ok()?
+                .parse::<u64>()
+                .ok()
+        }) {
+            Some(size) => total_size += size,
+            None => unknown_count += 1,
+        }
+    }
+
+    (total_size, unknown_count)
+}
+
+/// A file being downloaded, removed when it is dropped before the download completes so a truncated
+/// file is never picked up as a font file
+struct PartialFile<'a> {
+    file: Option<File>,
+    path: &'a Path,
+}
+
+impl<'a> PartialFile<'a> {
+    fn create(path: &'a Path) -> std::io::Result<Self> {
+        Ok(PartialFile {
+            file: Some(File::create(path)?),
+            path,
+        })
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.file.as_mut().unwrap().write_all(buf)
+    }
+
+    /// Closes the file and keeps it, once it is completely downloaded
+    fn keep(mut self) {
+        self.file.take();
+    }
+}
+
+impl Drop for PartialFile<'_> {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            drop(file);
+            std::fs::remove_file(self.path).ok();
+        }
+    }
 }
 
+/// Downloads a font file, or copies it out of the store when it is already in it. Returns the url the
+/// file was downloaded from after following redirects, its checksum and whether it came from the store
 async fn download_font_file(
     client: &Client,
     url: &str,
-    output_path: &PathBuf,
+    output_path: &Path,
+    retries: u32,
+    store: Option<&BlobStore>,
     progress_bar: ProgressBar,
-) -> Result<(), String> {
-    let response = client
-        .get(url)
-        .send()
+) -> Result<(String, String, bool), FileError> {
+    // The weight directories of --layout dir-per-weight are created as their files are downloaded
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|_| format!("Could not create directory: {}", parent.to_string_lossy()))?;
+    }
+    if let Some(sha256) = store.and_then(|store| store.copy_to(url, output_path)) {
+        return Ok((url.to_string(), sha256, true));
+    }
+
+    let started = Instant::now();
+    let response = get_with_retries(client, url, retries)
         .await
-        .map_err(|_| format!("Failed to GET from {}", url))?;
+        .map_err(|err| {
+            record_request("GET", url, err.status(), 0, started.elapsed());
+            format!("Failed to GET from {}: {}", url, err.without_url())
+        })?;
+    record_quota_headers(response.headers());
+    let status = response.status();
+    if !status.is_success() {
+        record_request("GET", url, Some(status), 0, started.elapsed());
+    }
+
+    if response.status().is_redirection() {
+        return Err(FileError::Failed(format!(
+            "{} redirects to {}, which is not followed with --no-redirects",
+            url,
+            response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .unwrap_or("an unknown location")
+        )));
+    }
+    if !response.status().is_success() {
+        return Err(FileError::Failed(format!(
+            "Failed to GET from {}: {}",
+            url,
+            response.status()
+        )));
+    }
+    let final_url = response.url().to_string();
 
     let total_size = response.content_length().unwrap_or(0);
     progress_bar.set_length(total_size);
 
-    let mut file = File::create(&output_path).map_err(|_| {
-        format!(
+    let mut file = PartialFile::create(output_path).map_err(|err| {
+        if is_disk_full(&err) {
+            return FileError::DiskFull(disk_full_message(output_path));
+        }
+        FileError::Failed(format!(
             "Failed to create file at: {}",
             output_path.to_string_lossy()
-        )
+        ))
     })?;
 
     let mut downloaded: u64 = 0;
+    let mut hasher = Sha256::new();
     let mut stream = response.bytes_stream();
 
     while let Some(item) = stream.next().await {
-        let chunk = item.map_err(|_| "Error while downloading file".to_string())?;
-        file.write_all(&chunk).map_err(|_| {
-            format!(
+        let chunk = item.map_err(|_| {
+            record_request("GET", url, Some(status), downloaded, started.elapsed());
+            "Error while downloading file".to_string()
+        })?;
+        if let Err(err) = file.write_all(&chunk) {
+            if is_disk_full(&err) {
+                return Err(FileError::DiskFull(disk_full_message(output_path)));
+            }
+            return Err(FileError::Failed(format!(
                 "Error while writing to file {}",
                 output_path.to_string_lossy()
-            )
-        })?;
+            )));
+        }
+        hasher.update(&chunk);
 
         downloaded += chunk.len() as u64;
         progress_bar.set_position(downloaded);
     }
 
+    record_request("GET", url, Some(status), downloaded, started.elapsed());
+    file.keep();
+
+    let sha256 = sha256_hex(hasher);
+    if let Some(store) = store {
+        // The file is already downloaded, so failing to add it to the store only costs a download next time
+        if let Err(err) = store.insert(url, output_path, &sha256) {
+            progress_bar.suspend(|| eprintln!("{}: {}", "warning".yellow(), err));
+        }
+    }
+
     // Don't finish or clear here - let the calling function handle it
     // This ensures proper coordination with the MultiProgress instance
-    Ok(())
+    Ok((final_url, sha256, false))
 }
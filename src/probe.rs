@@ -0,0 +1,199 @@
+use owo_colors::OwoColorize;
+use reqwest::{Client, StatusCode};
+use std::{env, error::Error, time::Instant};
+
+use crate::utils::{fetch_text_with_retries, ApiConfig};
+
+/// Font family requested by the probe, one that is always available
+const PROBE_FAMILY: &str = "Roboto";
+
+/// Environment variables reqwest reads the proxy from
+const PROXY_VARIABLES: &[&str] = &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"];
+
+/// Why the probe request failed
+#[derive(Debug, PartialEq, Eq)]
+enum ProbeFailure {
+    /// No connection could be established, e.g. no network, a wrong proxy or a firewall
+    Network,
+    /// A connection was established but the TLS handshake failed, e.g. an intercepting proxy
+    Tls,
+    Timeout,
+    /// The API rejected the api key
+    Auth,
+    /// The API answered with an error that is not about the api key, e.g. an exhausted quota
+    Api,
+}
+
+/// Whether any error in the chain of causes is a TLS or certificate error, which reqwest doesn't expose directly
+fn is_tls_error(err: &reqwest::Error) -> bool {
+    let mut source: Option<&dyn Error> = Some(err);
+    while let Some(err) = source {
+        let message = err.to_string().to_lowercase();
+        if ["certificate", "tls", "ssl", "handshake"]
+            .iter()
+            .any(|keyword| message.contains(keyword))
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Describes a request error by its causes, e.g. `could not connect: tcp connect error: Connection refused`
+fn error_chain(err: &reqwest::Error) -> String {
+    let mut messages = vec![if err.is_connect() {
+        "could not connect".to_string()
+    } else {
+        "request failed".to_string()
+    }];
+    let mut source = err.source();
+    while let Some(err) = source {
+        // Some errors already include the message of their cause
+        let message = err.to_string();
+        if !messages.last().is_some_and(|last| last.ends_with(&message)) {
+            messages.push(message);
+        }
+        source = err.source();
+    }
+    messages.join(": ")
+}
+
+/// Message of an error response of the API, e.g. `API key not valid. Please pass a valid API key.`
+fn api_error_message(body: &str) -> Option<String> {
+    let response: serde_json::Value = serde_json::from_str(body).ok()?;
+    response["error"]["message"].as_str().map(str::to_string)
+}
+
+/// Tells an api key the API rejected apart from other error responses
+fn classify_status(status: StatusCode, body: &str) -> Option<ProbeFailure> {
+    if status.is_success() {
+        return None;
+    }
+    let auth_failure = matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        || (status == StatusCode::BAD_REQUEST
+            && (body.contains("API_KEY_INVALID") || body.contains("API key not valid")));
+    Some(if auth_failure {
+        ProbeFailure::Auth
+    } else {
+        ProbeFailure::Api
+    })
+}
+
+fn print_ok(message: &str) {
+    println!(" {} {}", "=".green(), message);
+}
+
+fn print_problem(failure: ProbeFailure, message: &str) {
+    let label = match failure {
+        ProbeFailure::Network => "network",
+        ProbeFailure::Tls => "tls",
+        ProbeFailure::Timeout => "timeout",
+        ProbeFailure::Auth => "api key",
+        ProbeFailure::Api => "api",
+    };
+    println!(" {} {} {}", "!".red(), format!("{}:", label).red(), message);
+}
+
+/// Makes a single minimal authenticated request to the API and reports whether the network, TLS,
+/// proxy and api key work, with how long the request took. Returns whether the request succeeded
+pub(crate) async fn run_probe(client: &Client, api: &ApiConfig) -> Result<bool, String> {
+    if let Some((variable, proxy)) = PROXY_VARIABLES
+        .iter()
+        .find_map(|variable| Some((variable, env::var(variable).ok()?)))
+    {
+        print_ok(&format!("using the proxy {} from {}", proxy, variable));
+    }
+
+    let api_url = format!(
+        "{base_url}?key={key}&family={family}&fields=items(family)",
+        base_url = api.base_url,
+        key = api.key,
+        family = PROBE_FAMILY
+    );
+    let started = Instant::now();
+    let result = fetch_text_with_retries(client.get(&api_url), &api_url, 0).await;
+    let elapsed = started.elapsed();
+
+    let (status, body) = match result {
+        Ok(response) => response,
+        Err(err) => {
+            let failure = if err.is_timeout() {
+                ProbeFailure::Timeout
+            } else if is_tls_error(&err) {
+                ProbeFailure::Tls
+            } else {
+                ProbeFailure::Network
+            };
+            let hint = match failure {
+                ProbeFailure::Tls => {
+                    ", pass --danger-accept-invalid-certs behind a proxy you trust"
+                }
+                ProbeFailure::Timeout => ", pass a longer --connect-timeout on a slow network",
+                _ => "",
+            };
+            print_problem(
+                failure,
+                &format!(
+                    "{} after {}ms{}",
+                    error_chain(&err),
+                    elapsed.as_millis(),
+                    hint
+                ),
+            );
+            return Ok(false);
+        }
+    };
+
+    print_ok(&format!(
+        "connected to the api in {}",
+        format!("{}ms", elapsed.as_millis()).cyan()
+    ));
+    match classify_status(status, &body) {
+        None => {
+            print_ok(&format!("api key accepted, fetched {}", PROBE_FAMILY));
+            Ok(true)
+        }
+        Some(failure) => {
+            print_problem(
+                failure,
+                &format!(
+                    "the api responded with {}{}",
+                    status,
+                    api_error_message(&body)
+                        .map(|message| format!(": {}", message))
+                        .unwrap_or_default()
+                ),
+            );
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_status_tells_a_rejected_key_apart() {
+        let invalid_key = r#"{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT"}}"#;
+
+        assert_eq!(classify_status(StatusCode::OK, "{}"), None);
+        assert_eq!(
+            classify_status(StatusCode::BAD_REQUEST, invalid_key),
+            Some(ProbeFailure::Auth)
+        );
+        assert_eq!(
+            classify_status(StatusCode::FORBIDDEN, "{}"),
+            Some(ProbeFailure::Auth)
+        );
+        assert_eq!(
+            classify_status(StatusCode::TOO_MANY_REQUESTS, "{}"),
+            Some(ProbeFailure::Api)
+        );
+        assert_eq!(
+            api_error_message(invalid_key).as_deref(),
+            Some("API key not valid. Please pass a valid API key.")
+        );
+    }
+}
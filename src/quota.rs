@@ -0,0 +1,73 @@
+use owo_colors::OwoColorize;
+use reqwest::header::HeaderMap;
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Latest value of each quota header seen in a response, printed after a run with `--verbose`
+static QUOTA: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Prefixes of the headers google and the proxies in front of it report rate limits and quotas in
+const QUOTA_HEADER_PREFIXES: &[&str] =
+    &["x-ratelimit-", "ratelimit", "x-rate-limit-", "x-goog-quota"];
+
+/// Picks the quota headers out of the headers of a response
+fn quota_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            QUOTA_HEADER_PREFIXES
+                .iter()
+                .any(|prefix| name.as_str().starts_with(prefix))
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Records the quota headers of a response, replacing the values of earlier responses
+pub(crate) fn record_quota_headers(headers: &HeaderMap) {
+    let quota_headers = quota_headers(headers);
+    if quota_headers.is_empty() {
+        return;
+    }
+    QUOTA.lock().unwrap().extend(quota_headers);
+}
+
+/// Prints the quota reported by the latest responses, if any reported one
+pub(crate) fn print_quota() {
+    let quota = QUOTA.lock().unwrap();
+    if quota.is_empty() {
+        println!(
+            "{}",
+            "The API did not report any quota information".dimmed()
+        );
+        return;
+    }
+
+    println!("{}", "API quota reported by the latest responses".dimmed());
+    for (name, value) in quota.iter() {
+        println!(" {} {}", format!("{}:", name).dimmed(), value.cyan());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, CONTENT_TYPE};
+
+    #[test]
+    fn quota_headers_only_keeps_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("9990"));
+        headers.insert("x-goog-quota-user", HeaderValue::from_static("project"));
+
+        let mut quota = quota_headers(&headers);
+        quota.sort();
+        assert_eq!(
+            quota,
+            vec![
+                ("x-goog-quota-user".to_string(), "project".to_string()),
+                ("x-ratelimit-remaining".to_string(), "9990".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,514 @@
+use serde::Serialize;
+
+/// A parsed sfnt (ttf/otf) font file, giving access to its tables by tag
+pub(crate) struct Sfnt<'a> {
+    data: &'a [u8],
+    tables: Vec<TableRecord>,
+}
+
+/// Location of a table in the font file
+struct TableRecord {
+    tag: [u8; 4],
+    offset: usize,
+    length: usize,
+}
+
+/// A named instance of a variable font, e.g. `SemiBold Italic`, with its position on each axis
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NamedInstance {
+    pub name: String,
+    pub coordinates: Vec<(String, f32)>,
+}
+
+impl NamedInstance {
+    /// Position of the instance on an axis, e.g. `wght`
+    pub(crate) fn coordinate(&self, axis_tag: &str) -> Option<f32> {
+        self.coordinates
+            .iter()
+            .find(|(tag, _)| tag == axis_tag)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Vertical metrics of a font in font units, the ones browsers use to lay out lines of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct VerticalMetrics {
+    pub units_per_em: u16,
+    pub ascent: i16,
+    /// Distance below the baseline, positive unlike the descender in the font tables
+    pub descent: i16,
+    pub line_gap: i16,
+}
+
+/// Vertical metrics of a font along with the heights of its lowercase and capital letters,
+/// written to `metrics.json` with `--metrics-json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct FontMetrics {
+    #[serde(flatten)]
+    pub vertical: VerticalMetrics,
+    /// Height of lowercase letters, only in `OS/2` tables of version 2 and later
+    pub x_height: Option<i16>,
+    /// Height of capital letters, only in `OS/2` tables of version 2 and later
+    pub cap_height: Option<i16>,
+}
+
+/// Formats a distance in font units as a percentage of the em, e.g. `96.875%`
+fn em_percentage(value: i16, units_per_em: u16) -> String {
+    let percentage = format!("{:.3}", value as f64 * 100.0 / units_per_em as f64);
+    format!(
+        "{}%",
+        percentage.trim_end_matches('0').trim_end_matches('.')
+    )
+}
+
+impl VerticalMetrics {
+    /// `ascent-override`, `descent-override` and `line-gap-override` descriptors pinning the
+    /// metrics of an `@font-face` rule to these metrics
+    pub(crate) fn css_descriptors(&self) -> String {
+        format!(
+            "\tascent-override: {};\n\tdescent-override: {};\n\tline-gap-override: {};\n",
+            em_percentage(self.ascent, self.units_per_em),
+            em_percentage(self.descent, self.units_per_em),
+            em_percentage(self.line_gap, self.units_per_em)
+        )
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| "Unexpected end of font data".to_string())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| "Unexpected end of font data".to_string())
+}
+
+impl<'a> Sfnt<'a> {
+    /// Parses the table directory of a font file
+    pub(crate) fn parse(data: &'a [u8]) -> Result<Sfnt<'a>, String> {
+        let sfnt_version = read_u32(data, 0)?;
+        if sfnt_version != 0x0001_0000 && &data[..4] != b"OTTO" && &data[..4] != b"true" {
+            return Err("Not a ttf or otf font file".to_string());
+        }
+
+        let table_count = read_u16(data, 4)? as usize;
+        let mut tables = Vec::with_capacity(table_count);
+        for idx in 0..table_count {
+            let record_offset = 12 + idx * 16;
+            let tag = data
+                .get(record_offset..record_offset + 4)
+                .ok_or_else(|| "Unexpected end of font data".to_string())?;
+            let offset = read_u32(data, record_offset + 8)? as usize;
+            let length = read_u32(data, record_offset + 12)? as usize;
+            if offset + length > data.len() {
+                return Err("Font table extends past the end of the file".to_string());
+            }
+
+            tables.push(TableRecord {
+                tag: [tag[0], tag[1], tag[2], tag[3]],
+                offset,
+                length,
+            });
+        }
+
+        Ok(Sfnt { data, tables })
+    }
+
+    /// Gets the data of a table, e.g. `fvar`
+    pub(crate) fn table(&self, tag: &[u8; 4]) -> Option<&'a [u8]> {
+        self.tables
+            .iter()
+            .find(|table| &table.tag == tag)
+            .map(|table| &self.data[table.offset..table.offset + table.length])
+    }
+
+    /// Looks up a string in the `name` table, preferring the english windows record
+    pub(crate) fn name(&self, name_id: u16) -> Option<String> {
+        let name_table = self.table(b"name")?;
+        let record_count = read_u16(name_table, 2).ok()? as usize;
+        let string_offset = read_u16(name_table, 4).ok()? as usize;
+
+        let mut fallback = None;
+        for idx in 0..record_count {
+            let record_offset = 6 + idx * 12;
+            let platform_id = read_u16(name_table, record_offset).ok()?;
+            let language_id = read_u16(name_table, record_offset + 4).ok()?;
+            if read_u16(name_table, record_offset + 6).ok()? != name_id {
+                continue;
+            }
+
+            let length = read_u16(name_table, record_offset + 8).ok()? as usize;
+            let offset = string_offset + read_u16(name_table, record_offset + 10).ok()? as usize;
+            let bytes = name_table.get(offset..offset + length)?;
+
+            let name = match platform_id {
+                0 | 3 => String::from_utf16_lossy(
+                    &bytes
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect::<Vec<u16>>(),
+                ),
+                _ => bytes.iter().map(|&byte| byte as char).collect(),
+            };
+
+            if platform_id == 3 && language_id == 0x0409 {
+                return Some(name);
+            }
+            fallback.get_or_insert(name);
+        }
+
+        fallback
+    }
+
+    /// Reads the vertical metrics from the `OS/2` typo metrics when the font asks for them with
+    /// `USE_TYPO_METRICS`, otherwise from the `hhea` table like browsers on macOS do
+    pub(crate) fn vertical_metrics(&self) -> Result<VerticalMetrics, String> {
+        let head = self
+            .table(b"head")
+            .ok_or_else(|| "Font has no head table".to_string())?;
+        let units_per_em = read_u16(head, 18)?;
+        if units_per_em == 0 {
+            return Err("Font has no units per em".to_string());
+        }
+
+        let os2 = self
+            .table(b"OS/2")
+            .filter(|os2| read_u16(os2, 62).is_ok_and(|fs_selection| fs_selection & 1 << 7 != 0));
+        let (table, offset) = match os2 {
+            Some(os2) => (os2, 68),
+            None => (
+                self.table(b"hhea")
+                    .ok_or_else(|| "Font has no hhea table".to_string())?,
+                4,
+            ),
+        };
+        let descender = read_u16(table, offset + 2)? as i16;
+
+        Ok(VerticalMetrics {
+            units_per_em,
+            ascent: read_u16(table, offset)? as i16,
+            descent: descender.saturating_neg(),
+            line_gap: read_u16(table, offset + 4)? as i16,
+        })
+    }
+
+    /// Reads the vertical metrics along with the x-height and cap height from the `OS/2` table
+    pub(crate) fn font_metrics(&self) -> Result<FontMetrics, String> {
+        let os2 = self
+            .table(b"OS/2")
+            .filter(|os2| read_u16(os2, 0).is_ok_and(|version| version >= 2));
+        let read_height = |offset: usize| {
+            os2.and_then(|os2| read_u16(os2, offset).ok().map(|value| value as i16))
+        };
+
+        Ok(FontMetrics {
+            vertical: self.vertical_metrics()?,
+            x_height: read_height(86),
+            cap_height: read_height(88),
+        })
+    }
+
+    /// Reads the named instances of a variable font from its `fvar` table.
+    /// Returns an empty list for static fonts
+    pub(crate) fn named_instances(&self) -> Result<Vec<NamedInstance>, String> {
+        let Some(fvar) = self.table(b"fvar") else {
+            return Ok(vec![]);
+        };
+
+        let axes_offset = read_u16(fvar, 4)? as usize;
+        let axis_count = read_u16(fvar, 8)? as usize;
+        let axis_size = read_u16(fvar, 10)? as usize;
+        let instance_count = read_u16(fvar, 12)? as usize;
+        let instance_size = read_u16(fvar, 14)? as usize;
+
+        let mut axis_tags = Vec::with_capacity(axis_count);
+        for idx in 0..axis_count {
+            let axis_offset = axes_offset + idx * axis_size;
+            let tag = fvar
+                .get(axis_offset..axis_offset + 4)
+                .ok_or_else(|| "Unexpected end of font data".to_string())?;
+            axis_tags.push(String::from_utf8_lossy(tag).trim().to_string());
+        }
+
+        let instances_offset = axes_offset + axis_count * axis_size;
+        let mut named_instances = Vec::with_capacity(instance_count);
+        for idx in 0..instance_count {
+            let instance_offset = instances_offset + idx * instance_size;
+            let subfamily_name_id = read_u16(fvar, instance_offset)?;
+
+            let mut coordinates = Vec::with_capacity(axis_count);
+            for (axis_idx, axis_tag) in axis_tags.iter().enumerate() {
+                let fixed = read_u32(fvar, instance_offset + 4 + axis_idx * 4)? as i32;
+                coordinates.push((axis_tag.clone(), fixed as f32 / 65536.0));
+            }
+
+            named_instances.push(NamedInstance {
+                name: self
+                    .name(subfamily_name_id)
+                    .unwrap_or_else(|| format!("Instance {}", subfamily_name_id)),
+                coordinates,
+            });
+        }
+
+        Ok(named_instances)
+    }
+}
+
+/// Tables fonts render without: the digital signature, which stripping any table invalidates anyway,
+/// and the tables font editors and hinting tools leave behind
+const STRIPPED_TABLES: &[&[u8; 4]] = &[
+    b"DSIG", b"Debg", b"FFTM", b"PfEd", b"TSI0", b"TSI1", b"TSI2", b"TSI3", b"TSI5", b"TSIB",
+    b"TSID", b"TSIJ", b"TSIP", b"TSIV",
+];
+
+/// Descriptive name records browsers never read: trademark, manufacturer, designer, description,
+/// vendor and designer urls and the sample text. The copyright and license records are kept
+const STRIPPED_NAME_IDS: &[u16] = &[7, 8, 9, 10, 11, 12, 19];
+
+/// Rebuilds the `name` table without the descriptive records, and without the mac records of
+/// names that also have a windows record. Tables in a format other than 0 are returned as they are
+fn strip_name_table(name_table: &[u8]) -> Result<Vec<u8>, String> {
+    if read_u16(name_table, 0)? != 0 {
+        return Ok(name_table.to_vec());
+    }
+    let record_count = read_u16(name_table, 2)? as usize;
+    let string_offset = read_u16(name_table, 4)? as usize;
+
+    let mut records = Vec::with_capacity(record_count);
+    for idx in 0..record_count {
+        let record_offset = 6 + idx * 12;
+        let record = name_table
+            .get(record_offset..record_offset + 12)
+            .ok_or_else(|| "Unexpected end of font data".to_string())?;
+        records.push(record);
+    }
+    let windows_name_ids: Vec<u16> = records
+        .iter()
+        .filter(|record| read_u16(record, 0) == Ok(3))
+        .filter_map(|record| read_u16(record, 6).ok())
+        .collect();
+
+    let mut kept_records = vec![];
+    let mut strings = vec![];
+    for record in records {
+        let platform_id = read_u16(record, 0)?;
+        let name_id = read_u16(record, 6)?;
+        if STRIPPED_NAME_IDS.contains(&name_id)
+            || (platform_id == 1 && windows_name_ids.contains(&name_id))
+        {
+            continue;
+        }
+
+        let length = read_u16(record, 8)? as usize;
+        let offset = string_offset + read_u16(record, 10)? as usize;
+        let string = name_table
+            .get(offset..offset + length)
+            .ok_or_else(|| "Unexpected end of font data".to_string())?;
+        let mut kept_record = record[..10].to_vec();
+        kept_record.extend_from_slice(&(strings.len() as u16).to_be_bytes());
+        kept_records.push(kept_record);
+        strings.extend_from_slice(string);
+    }
+
+    let mut stripped = vec![];
+    stripped.extend_from_slice(&0u16.to_be_bytes());
+    stripped.extend_from_slice(&(kept_records.len() as u16).to_be_bytes());
+    stripped.extend_from_slice(&(6 + kept_records.len() as u16 * 12).to_be_bytes());
+    for record in kept_records {
+        stripped.extend_from_slice(&record);
+    }
+    stripped.extend_from_slice(&strings);
+
+    Ok(stripped)
+}
+
+/// Sums a table as big endian u32 words, padding the last word with zeros
+fn table_checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Writes a font file from its tables, laid out in tag order with the checksums recalculated
+fn write_sfnt(sfnt_version: u32, mut tables: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    tables.sort_by_key(|(tag, _)| *tag);
+    let table_count = tables.len() as u16;
+    let entry_selector = (table_count.max(1)).ilog2() as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+
+    let mut data = vec![];
+    data.extend_from_slice(&sfnt_version.to_be_bytes());
+    data.extend_from_slice(&table_count.to_be_bytes());
+    data.extend_from_slice(&search_range.to_be_bytes());
+    data.extend_from_slice(&entry_selector.to_be_bytes());
+    data.extend_from_slice(&(table_count * 16 - search_range).to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = None;
+    for (tag, table) in &mut tables {
+        if tag == b"head" && table.len() >= 12 {
+            // The checksum adjustment is left out of the checksums and set once the whole file is written
+            table[8..12].copy_from_slice(&[0; 4]);
+            head_offset = Some(offset);
+        }
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&table_checksum(table).to_be_bytes());
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+        data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += table.len().next_multiple_of(4);
+    }
+    for (_, table) in &tables {
+        data.extend_from_slice(table);
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(table_checksum(&data));
+        data[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+
+    data
+}
+
+/// Removes the tables and name records a font renders without from a ttf or otf font file,
+/// keeping every other table as it is
+pub(crate) fn strip_metadata(data: &[u8]) -> Result<Vec<u8>, String> {
+    let sfnt = Sfnt::parse(data)?;
+
+    let mut tables = vec![];
+    for table in &sfnt.tables {
+        if STRIPPED_TABLES.contains(&&table.tag) {
+            continue;
+        }
+        let table_data = &data[table.offset..table.offset + table.length];
+        let table_data = match &table.tag {
+            b"name" => strip_name_table(table_data)?,
+            _ => table_data.to_vec(),
+        };
+        tables.push((table.tag, table_data));
+    }
+
+    Ok(write_sfnt(read_u32(data, 0)?, tables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a format 0 `name` table with a windows english record for each name
+    fn name_table(names: &[(u16, &str)]) -> Vec<u8> {
+        let mut records = vec![];
+        let mut strings = vec![];
+        for (name_id, name) in names {
+            let string: Vec<u8> = name.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            for value in [
+                3,
+                1,
+                0x0409,
+                *name_id,
+                string.len() as u16,
+                strings.len() as u16,
+            ] {
+                records.extend_from_slice(&value.to_be_bytes());
+            }
+            strings.extend_from_slice(&string);
+        }
+
+        let mut table = vec![];
+        for value in [0, names.len() as u16, 6 + names.len() as u16 * 12] {
+            table.extend_from_slice(&value.to_be_bytes());
+        }
+        table.extend_from_slice(&records);
+        table.extend_from_slice(&strings);
+        table
+    }
+
+    #[test]
+    fn strip_metadata_drops_the_signature_and_descriptive_names() {
+        let font = write_sfnt(
+            0x0001_0000,
+            vec![
+                (*b"head", vec![0; 54]),
+                (*b"DSIG", vec![0, 0, 0, 1, 0, 0, 0, 0]),
+                (
+                    *b"name",
+                    name_table(&[(1, "Inter"), (9, "Rasmus Andersson"), (13, "OFL")]),
+                ),
+            ],
+        );
+
+        let stripped = strip_metadata(&font).unwrap();
+        assert!(stripped.len() < font.len());
+
+        let sfnt = Sfnt::parse(&stripped).unwrap();
+        assert!(sfnt.table(b"DSIG").is_none());
+        assert_eq!(sfnt.name(1).as_deref(), Some("Inter"));
+        assert_eq!(sfnt.name(9), None);
+        assert_eq!(sfnt.name(13).as_deref(), Some("OFL"));
+        assert_eq!(table_checksum(&stripped), 0xB1B0_AFBA);
+    }
+
+    #[test]
+    fn vertical_metrics_prefer_typo_metrics_when_asked_to() {
+        let mut head = vec![0; 54];
+        head[18..20].copy_from_slice(&2048u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        for (offset, value) in [(4, 1984i16), (6, -494), (8, 0)] {
+            hhea[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+        }
+        let mut os2 = vec![0; 96];
+        for (offset, value) in [(68, 1536i16), (70, -512), (72, 102)] {
+            os2[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+        }
+        let font = |os2: Vec<u8>| {
+            write_sfnt(
+                0x0001_0000,
+                vec![
+                    (*b"head", head.clone()),
+                    (*b"hhea", hhea.clone()),
+                    (*b"OS/2", os2),
+                ],
+            )
+        };
+
+        let hhea_font = font(os2.clone());
+        let metrics = Sfnt::parse(&hhea_font).unwrap().vertical_metrics().unwrap();
+        assert_eq!(
+            metrics.css_descriptors(),
+            "\tascent-override: 96.875%;\n\tdescent-override: 24.121%;\n\tline-gap-override: 0%;\n"
+        );
+
+        let font_metrics = Sfnt::parse(&hhea_font).unwrap().font_metrics().unwrap();
+        assert_eq!(
+            (font_metrics.x_height, font_metrics.cap_height),
+            (None, None)
+        );
+
+        os2[0..2].copy_from_slice(&4u16.to_be_bytes());
+        for (offset, value) in [(86, 1118i16), (88, 1490)] {
+            os2[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+        }
+        os2[62..64].copy_from_slice(&(1u16 << 7).to_be_bytes());
+        let typo_font = font(os2);
+        let font_metrics = Sfnt::parse(&typo_font).unwrap().font_metrics().unwrap();
+        assert_eq!(
+            (font_metrics.x_height, font_metrics.cap_height),
+            (Some(1118), Some(1490))
+        );
+        let metrics = Sfnt::parse(&typo_font).unwrap().vertical_metrics().unwrap();
+        assert_eq!(
+            metrics,
+            VerticalMetrics {
+                units_per_em: 2048,
+                ascent: 1536,
+                descent: 512,
+                line_gap: 102,
+            }
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::utils::{sha256_file, sha256_hex};
+
+/// Content-addressed store of downloaded font files shared by every project on the machine, enabled
+/// with `--store`. Each file is kept once in `blobs/<sha256>`, and `urls/<sha256 of the url>` records
+/// which blob a url was downloaded to. Font urls on google fonts include the version, so they never change
+#[derive(Debug, Clone)]
+pub(crate) struct BlobStore {
+    dir: PathBuf,
+}
+
+/// Directory of the store, `$XDG_CACHE_HOME/gfontapi` or `~/.cache/gfontapi`
+fn get_store_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|cache_home| !cache_home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from(".cache"))
+        .join("gfontapi")
+}
+
+/// Writes a file next to its destination and renames it into place, so concurrent installs never
+/// see a partially written file
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&Path) -> std::io::Result<()>,
+) -> Result<(), String> {
+    let temp_path = path.with_extension(format!("tmp-{}", process::id()));
+    write(&temp_path)
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .map_err(|_| {
+            std::fs::remove_file(&temp_path).ok();
+            format!("Could not create file at path: {:?}", path)
+        })
+}
+
+impl BlobStore {
+    /// Opens the store, creating its directories when they don't exist yet
+    pub(crate) fn open() -> Result<BlobStore, String> {
+        let store = BlobStore {
+            dir: get_store_dir(),
+        };
+        for dir in [store.dir.join("blobs"), store.dir.join("urls")] {
+            std::fs::create_dir_all(&dir)
+                .map_err(|_| format!("Could not create directory: {}", dir.to_string_lossy()))?;
+        }
+        Ok(store)
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+
+    fn url_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join("urls")
+            .join(sha256_hex(Sha256::new_with_prefix(url)))
+    }
+
+    /// Copies the file downloaded from a url out of the store. Returns its checksum, or `None` when the
+    /// url was never downloaded to the store or its blob no longer matches its checksum
+    pub(crate) fn copy_to(&self, url: &str, output_path: &Path) -> Option<String> {
+        let sha256 = std::fs::read_to_string(self.url_path(url)).ok()?;
+        let sha256 = sha256.trim();
+        let blob_path = self.blob_path(sha256);
+        if sha256_file(&blob_path).ok()? != sha256 {
+            std::fs::remove_file(&blob_path).ok();
+            return None;
+        }
+
+        // Copied rather than hard linked, so changing the file in a project never changes the blob
+        std::fs::copy(&blob_path, output_path).ok()?;
+        Some(sha256.to_string())
+    }
+
+    /// Adds a file downloaded from a url to the store, unless a file with the same checksum is already in it
+    pub(crate) fn insert(&self, url: &str, path: &Path, sha256: &str) -> Result<(), String> {
+        let blob_path = self.blob_path(sha256);
+        if !blob_path.is_file() {
+            write_atomically(&blob_path, |temp_path| {
+                std::fs::copy(path, temp_path).map(|_| ())
+            })?;
+        }
+        write_atomically(&self.url_path(url), |temp_path| {
+            std::fs::write(temp_path, sha256)
+        })
+    }
+}
@@ -1,28 +1,70 @@
-use std::{env, fs::OpenOptions, path::PathBuf, process};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    env,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
+use clap::ValueEnum;
 use owo_colors::OwoColorize;
-use std::io::Write;
+use reqwest::{redirect::Policy, Client, RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::IsTerminal;
 use subprocess::{Popen, PopenConfig, Redirection};
 
-use crate::FontStyles;
+use crate::{
+    audit::record_request,
+    css2::Css2Face,
+    error::GfontError,
+    fonts::{css_format, url_extension, FileNaming, FilenameStyle, FontFormat, FontStyles, Layout},
+    quota::record_quota_headers,
+    sfnt::{FontMetrics, VerticalMetrics},
+};
 
 /// Gets the path to the `woff2_compress` binary.
 /// Looks for `woff2_compress` in `~/.gfontapi/bin` and `/usr/local/bin` if not found, returns an error
 pub fn get_woff2_compress() -> Result<PathBuf, String> {
     let binary_exists: Vec<PathBuf> = [
-        "/usr/local/bin/woff2_compress",
-        "~/.gfontapi/bin/woff2_compress",
+        PathBuf::from("/usr/local/bin/woff2_compress"),
+        get_gfontapi_dir().join("bin").join("woff2_compress"),
     ]
-    .iter()
-    .map(|x| PathBuf::from(x))
+    .into_iter()
     .filter(|x| x.exists())
     .collect();
-    if binary_exists.len() == 0 {
-        return Err(format!("Could not locate woff2_compress binary on system"));
+    if binary_exists.is_empty() {
+        return Err("Could not locate woff2_compress binary on system".to_string());
     }
     Ok(binary_exists[0].clone())
 }
 
+/// Casing of the slugs used for the directory and file names of a font family
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum SlugStyle {
+    /// Lowercase words separated by dashes, e.g. `dm-sans`
+    #[default]
+    Kebab,
+    /// Lowercase words separated by underscores, e.g. `dm_sans`
+    Snake,
+    /// Capitalized words without separators, e.g. `DmSans`
+    Pascal,
+}
+
+/// Turns a font family name into the slug used for its directory and file names,
+/// e.g. `DM Sans` becomes `dm-sans`, `dm_sans` or `DmSans`
+pub(crate) fn slugify(font_family: &str, slug_style: SlugStyle) -> String {
+    let words = font_family.split_whitespace().map(str::to_lowercase);
+    match slug_style {
+        SlugStyle::Kebab => words.collect::<Vec<String>>().join("-"),
+        SlugStyle::Snake => words.collect::<Vec<String>>().join("_"),
+        SlugStyle::Pascal => words
+            .map(|word| format_font_string(&word))
+            .collect::<Vec<String>>()
+            .join(""),
+    }
+}
+
 /// Convert the font name to kebab case
 pub fn format_font_string(input: &str) -> String {
     input
@@ -41,66 +83,1419 @@ pub fn format_font_string(input: &str) -> String {
         .join(" ")
 }
 
+/// Order of the `@font-face` rules in generated stylesheets
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum CssOrder {
+    /// Ascending weight from thin to black, the normal style before the italic one
+    #[default]
+    Weights,
+    /// Alphabetical by style name, e.g. `black` before `bold`
+    Alpha,
+}
+
+/// How the font family name is quoted in the `font-family` of generated css
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum CssQuote {
+    /// `"Open Sans"`
+    #[default]
+    Double,
+    /// `'Open Sans'`
+    Single,
+    /// Unquoted when the name is valid css without quotes, e.g. `Open Sans`, double quotes otherwise
+    Auto,
+}
+
+/// Line terminator of the generated css
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum LineEnding {
+    Lf,
+    Crlf,
+    /// `crlf` on windows, `lf` everywhere else
+    #[default]
+    Native,
+}
+
+impl LineEnding {
+    /// Replaces the `\n` line terminators of generated css with this line ending
+    pub(crate) fn apply(&self, text: &str) -> String {
+        let crlf = match self {
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+            LineEnding::Native => cfg!(windows),
+        };
+        if crlf {
+            text.replace("\r\n", "\n").replace('\n', "\r\n")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Font family names that are css keywords, so they have to be quoted to name a font
+const CSS_FONT_KEYWORDS: &[&str] = &[
+    "serif",
+    "sans-serif",
+    "monospace",
+    "cursive",
+    "fantasy",
+    "system-ui",
+    "math",
+    "emoji",
+    "fangsong",
+    "ui-serif",
+    "ui-sans-serif",
+    "ui-monospace",
+    "ui-rounded",
+    "inherit",
+    "initial",
+    "unset",
+    "revert",
+    "revert-layer",
+    "default",
+];
+
+impl CssQuote {
+    /// Quotes a font family name for the `font-family` property
+    pub(crate) fn quote(&self, font_family_name: &str) -> String {
+        match self {
+            CssQuote::Single => format!("'{}'", font_family_name.replace('\'', "\\'")),
+            CssQuote::Auto if !needs_quotes(font_family_name) => font_family_name.to_string(),
+            CssQuote::Double | CssQuote::Auto => {
+                format!("\"{}\"", font_family_name.replace('"', "\\\""))
+            }
+        }
+    }
+}
+
+/// Whether a font family name has to be quoted: unquoted names are a sequence of css identifiers
+/// that aren't keywords, e.g. `Open Sans` but not `Press Start 2P`
+fn needs_quotes(font_family_name: &str) -> bool {
+    font_family_name.split(' ').any(|word| {
+        let mut chars = word.chars();
+        let starts_like_identifier = match chars.next() {
+            Some('-') => chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_'),
+            Some(c) => c.is_ascii_alphabetic() || c == '_',
+            None => false,
+        };
+        !starts_like_identifier
+            || !word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            || CSS_FONT_KEYWORDS.contains(&word.to_ascii_lowercase().as_str())
+    })
+}
+
+/// Sources listed in the `src` of an `@font-face` rule
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CssSource {
+    /// The font installed on the device, by its full and postscript name
+    Local,
+    Woff2,
+    Ttf,
+}
+
+impl CssSource {
+    /// Extension of the font file the source points at, `None` for `local()`
+    pub(crate) fn extension(&self) -> Option<&'static str> {
+        match self {
+            CssSource::Local => None,
+            CssSource::Woff2 => Some("woff2"),
+            CssSource::Ttf => Some("ttf"),
+        }
+    }
+}
+
+/// Options controlling how the stylesheets for a font family are generated
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CssOptions {
+    /// Naming scheme of the font files referenced by the stylesheet
+    pub naming: FileNaming,
+    /// Fallback font families at the end of generated font stacks
+    pub fallback: String,
+    /// Server path the font urls are rooted at, always ends with a `/`
+    pub base_url: Option<String>,
+    /// Directory the stylesheets are written to instead of the font directory
+    pub css_dir: Option<PathBuf>,
+    /// Directory the font urls are relative to instead of the directory of the stylesheet, e.g. the web root
+    pub relative_to: Option<PathBuf>,
+    /// Label each `@font-face` rule with a comment naming its font style
+    pub comment_per_face: bool,
+    /// Font files referenced by the `src` of each `@font-face` rule
+    pub font_format: FontFormat,
+    /// Name of the font family in the `font-family` of each rule, derived from the font directory when not set
+    pub display_name: Option<String>,
+    /// Style names used for weights in comments and class names instead of the standard ones, e.g. `600` as `demi`
+    pub weight_labels: BTreeMap<u16, String>,
+    /// Style names of `--weight-aliases` used for weights in the font file names with named naming
+    pub file_weight_labels: BTreeMap<u16, String>,
+    /// Order of the `@font-face` rules
+    pub order: CssOrder,
+    /// Weight range covered by each font file when they are variable fonts, e.g. `(100, 900)`
+    pub weight_range: Option<(u16, u16)>,
+    /// Weights that are not available mapped to the available weight whose files stand in for them
+    pub weight_fallbacks: BTreeMap<u16, u16>,
+    /// Add a utility class for each font style after the `@font-face` rules, e.g. `.font-inter-bold`
+    pub utility_classes: bool,
+    /// Declare the font family and its fallback stack in a css variable on `:root`, e.g. `--font-inter`
+    pub family_stack: bool,
+    /// Sources of the `src` of each rule in this exact order, instead of the font files in order of preference
+    pub src_order: Option<Vec<CssSource>>,
+    /// Quotes around the font family name in `font-family`
+    pub quote: CssQuote,
+    /// Whether the font file names in the urls start with the font family name
+    pub filename_style: FilenameStyle,
+    /// Layout of the font files in the font directory
+    pub layout: Layout,
+    /// Line terminator of the stylesheets
+    pub line_ending: LineEnding,
+    /// Leave stylesheets that already have the generated content alone instead of writing them again
+    pub skip_if_unchanged: bool,
+    /// Vertical metrics of the font file of each font style, pinned in its `@font-face` rules
+    pub metrics: HashMap<FontStyles, VerticalMetrics>,
+}
+
+impl CssOptions {
+    /// Compares two font styles by the order their rules are written in
+    fn compare_font_styles(&self, a: &FontStyles, b: &FontStyles) -> Ordering {
+        match self.order {
+            CssOrder::Weights => {
+                let (a_style, a_weight) = a.get_style_and_weight();
+                let (b_style, b_weight) = b.get_style_and_weight();
+                (a_weight, a_style == "italic").cmp(&(b_weight, b_style == "italic"))
+            }
+            CssOrder::Alpha => a
+                .label(&self.weight_labels)
+                .cmp(&b.label(&self.weight_labels)),
+        }
+    }
+
+    /// Copy of the font styles sorted in the order their rules are written in
+    fn sorted_font_styles(&self, font_styles: &[FontStyles]) -> Vec<FontStyles> {
+        let mut font_styles = font_styles.to_vec();
+        font_styles.sort_by(|a, b| self.compare_font_styles(a, b));
+        font_styles
+    }
+
+    /// Font styles with the weight of their `@font-face` rule, in the order the rules are written in.
+    /// The files of a font style get another rule for each missing weight they stand in for
+    fn font_faces(&self, font_styles: &[FontStyles]) -> Vec<(FontStyles, u16)> {
+        let font_styles = self.sorted_font_styles(font_styles);
+        let mut font_faces: Vec<(FontStyles, u16)> = font_styles
+            .iter()
+            .map(|font_style| (font_style.clone(), font_style.get_style_and_weight().1))
+            .collect();
+
+        for (&missing_weight, &available_weight) in &self.weight_fallbacks {
+            for font_style in &font_styles {
+                if font_style.get_style_and_weight().1 == available_weight {
+                    font_faces.push((font_style.clone(), missing_weight));
+                }
+            }
+        }
+        if self.order == CssOrder::Weights {
+            font_faces.sort_by_key(|(font_style, font_weight)| {
+                (
+                    *font_weight,
+                    font_style.get_style_and_weight().0 == "italic",
+                )
+            });
+        }
+
+        font_faces
+    }
+
+    /// Name used for the `font-family` of the generated rules, e.g. `Dm Sans`
+    fn display_name(&self, font_dir: &Path) -> String {
+        self.display_name
+            .clone()
+            .unwrap_or_else(|| format_font_string(&font_dir.file_name().unwrap().to_string_lossy()))
+    }
+
+    /// Path of the font file of a font style relative to the font directory, as it is referenced in urls
+    fn font_file_path(
+        &self,
+        font_style: &FontStyles,
+        font_family_name: &str,
+        extension: &str,
+    ) -> String {
+        self.layout.file_path(
+            font_style,
+            &font_style.labeled_file_name(
+                self.filename_style.prefix(font_family_name),
+                self.naming,
+                &self.file_weight_labels,
+                extension,
+            ),
+        )
+    }
+
+    /// Path of the stylesheet for a font family, `fonts.css` in the font directory unless
+    /// the stylesheets are written to a separate css directory
+    pub(crate) fn css_file_path(
+        &self,
+        font_dir: &Path,
+        font_family_name: &str,
+        extension: &str,
+    ) -> PathBuf {
+        match &self.css_dir {
+            Some(css_dir) => css_dir.join(format!("{}.{}", font_family_name, extension)),
+            None => font_dir.join(format!("fonts.{}", extension)),
+        }
+    }
+}
+
+/// Gets the path of `path` relative to the directory `base_dir`, both of which have to exist
+pub fn relative_path(base_dir: &Path, path: &Path) -> Result<PathBuf, String> {
+    let base_dir = base_dir
+        .canonicalize()
+        .map_err(|_| format!("Could not resolve path: {}", base_dir.to_string_lossy()))?;
+    let path = path
+        .canonicalize()
+        .map_err(|_| format!("Could not resolve path: {}", path.to_string_lossy()))?;
+
+    let common_count = base_dir
+        .components()
+        .zip(path.components())
+        .take_while(|(base_component, component)| base_component == component)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in base_dir.components().skip(common_count) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common_count) {
+        relative.push(component);
+    }
+
+    Ok(relative)
+}
+
+/// Builds the prefix of the font `url()`s in a stylesheet. The urls are rooted at the base url when one
+/// is set, relative to the reference directory when one is set, and relative to the css directory
+/// when the stylesheets are written to a separate directory
+fn font_url_prefix(font_dir: &Path, options: &CssOptions) -> Result<String, String> {
+    if let Some(base_url) = &options.base_url {
+        return Ok(base_url.clone());
+    }
+    let font_dir = match (&options.relative_to, &options.css_dir) {
+        (Some(relative_to), _) => relative_path(relative_to, font_dir)?,
+        (None, Some(css_dir)) => relative_path(css_dir, font_dir)?,
+        (None, None) => font_dir.to_path_buf(),
+    };
+
+    if font_dir.as_os_str().is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!(
+        "{}/",
+        font_dir
+            .to_string_lossy()
+            .replace('\\', "/")
+            .trim_end_matches('/')
+    ))
+}
+
+/// Builds the `@font-face` rule for a single font style of a font family, at `font_weight` which is
+/// the weight of the font style unless its file stands in for a missing weight
+fn font_face_rule(
+    font_style: &FontStyles,
+    font_weight: u16,
+    url_prefix: &str,
+    font_family_name: &str,
+    font_family_display_name: &str,
+    options: &CssOptions,
+) -> String {
+    let font_file_source = |extension: &str| {
+        format!(
+            "url(\"{}{}\") format(\"{}\")",
+            url_prefix,
+            options.font_file_path(font_style, font_family_name, extension),
+            css_format(extension)
+        )
+    };
+    let sources = match (&options.src_order, options.font_format) {
+        (Some(src_order), _) => src_order
+            .iter()
+            .map(|source| match source.extension() {
+                Some(extension) => font_file_source(extension),
+                None => {
+                    let style_name = format_font_string(&font_style.to_string());
+                    format!(
+                        "local(\"{} {}\"), local(\"{}-{}\")",
+                        font_family_display_name,
+                        style_name,
+                        font_family_display_name.replace(' ', ""),
+                        style_name.replace(' ', "")
+                    )
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", "),
+        (None, FontFormat::Woff2) => format!(
+            "url(\"{}{}\")",
+            url_prefix,
+            options.font_file_path(font_style, font_family_name, "woff2")
+        ),
+        (None, font_format) => font_format
+            .extensions()
+            .iter()
+            .map(|extension| font_file_source(extension))
+            .collect::<Vec<String>>()
+            .join(", "),
+    };
+
+    font_face_rule_with_sources(
+        font_style,
+        font_weight,
+        &sources,
+        font_family_display_name,
+        options,
+    )
+}
+
+/// Builds the `@font-face` rule for a font style whose `src` is already formatted
+fn font_face_rule_with_sources(
+    font_style: &FontStyles,
+    font_weight: u16,
+    sources: &str,
+    font_family_display_name: &str,
+    options: &CssOptions,
+) -> String {
+    let (font_style_name, _) = font_style.get_style_and_weight();
+    let font_weight = match options.weight_range {
+        Some((start, end)) => format!("{} {}", start, end),
+        None => font_weight.to_string(),
+    };
+    let comment = if options.comment_per_face {
+        format!(
+            "/* {} {} ({}) */\n",
+            font_family_display_name,
+            format_font_string(&font_style.label(&options.weight_labels)),
+            font_weight
+        )
+    } else {
+        String::new()
+    };
+
+    let metrics = options
+        .metrics
+        .get(font_style)
+        .map(VerticalMetrics::css_descriptors)
+        .unwrap_or_default();
+
+    format!(
+        "{}@font-face {{\n\tfont-family: {};\n\tsrc: {};\n\tfont-style: {};\n\tfont-weight: {};\n{}}}\n",
+        comment,
+        options.quote.quote(font_family_display_name),
+        sources,
+        font_style_name,
+        font_weight,
+        metrics
+    )
+}
+
+/// Builds a `:root` rule declaring the font stack in a css variable, e.g. `--font-inter: "Inter", system-ui, sans-serif`,
+/// or nothing when the family stack is turned off
+fn family_stack_rule(
+    font_family_name: &str,
+    font_family_display_name: &str,
+    options: &CssOptions,
+) -> String {
+    if !options.family_stack {
+        return String::new();
+    }
+
+    format!(
+        ":root {{\n\t--font-{}: {}, {};\n}}\n\n",
+        font_family_name,
+        options.quote.quote(font_family_display_name),
+        options.fallback
+    )
+}
+
+/// Builds a utility class setting the font family, style and weight for each font style,
+/// e.g. `.font-inter-bold`, or nothing when utility classes are turned off
+fn utility_classes(
+    font_styles: &[FontStyles],
+    font_family_name: &str,
+    font_family_display_name: &str,
+    options: &CssOptions,
+) -> String {
+    if !options.utility_classes {
+        return String::new();
+    }
+
+    options
+        .sorted_font_styles(font_styles)
+        .iter()
+        .map(|font_style| {
+            let (font_style_name, font_weight) = font_style.get_style_and_weight();
+            format!(
+                ".font-{}-{} {{\n\tfont-family: {}, {};\n\tfont-style: {};\n\tfont-weight: {};\n}}\n\n",
+                font_family_name,
+                font_style.label(&options.weight_labels),
+                options.quote.quote(font_family_display_name),
+                options.fallback,
+                font_style_name,
+                font_weight
+            )
+        })
+        .collect()
+}
+
+/// Writes a stylesheet with the line endings of the options. With `--skip-css-if-unchanged` a stylesheet
+/// that already has this content is left alone, so file watchers don't see a change
+fn write_stylesheet(css_file_path: &Path, css: &str, options: &CssOptions) -> Result<(), String> {
+    let css = options.line_ending.apply(css);
+    if options.skip_if_unchanged
+        && std::fs::read_to_string(css_file_path).is_ok_and(|existing| existing == css)
+    {
+        return Ok(());
+    }
+
+    write_generated_file(css_file_path, css)
+}
+
+/// Writes a file generated for the installed fonts, removing what was written of it when the disk is full
+fn write_generated_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|e| {
+        if is_disk_full(&e) {
+            std::fs::remove_file(path).ok();
+            return disk_full_message(path);
+        }
+        format!("Could not create file at path: {:?}", path)
+    })
+}
+
 /// Writes a css file for a font family to the font directory.
 /// Creates an `@font-face` rule for each font style in the downloaded fonts
 pub(crate) fn write_css_file_for_font(
     font_styles: &[FontStyles],
-    font_dir: &PathBuf,
+    font_dir: &Path,
     font_family_name: &str,
+    options: &CssOptions,
 ) -> Result<String, String> {
-    let css_file_path = font_dir.join("fonts.css");
-    let font_family_display_name =
-        format_font_string(&font_dir.file_name().unwrap().to_string_lossy().to_string());
+    let css_file_path = options.css_file_path(font_dir, font_family_name, "css");
+    let font_family_display_name = options.display_name(font_dir);
+    let url_prefix = font_url_prefix(font_dir, options)?;
 
-    for (idx, font_style) in font_styles.iter().enumerate() {
-        let (font_style_name, font_weight) = font_style.get_style_and_weight();
+    // A family whose downloads all failed keeps the stylesheet of its last install
+    let font_faces = options.font_faces(font_styles);
+    if font_faces.is_empty() {
+        return Ok(css_file_path.to_string_lossy().into());
+    }
+    let mut css: String = font_faces
+        .iter()
+        .map(|(font_style, font_weight)| {
+            font_face_rule(
+                font_style,
+                *font_weight,
+                &url_prefix,
+                font_family_name,
+                &font_family_display_name,
+                options,
+            ) + "\n"
+        })
+        .collect();
+    css.push_str(&family_stack_rule(
+        font_family_name,
+        &font_family_display_name,
+        options,
+    ));
+    css.push_str(&utility_classes(
+        font_styles,
+        font_family_name,
+        &font_family_display_name,
+        options,
+    ));
+
+    write_stylesheet(&css_file_path, &css, options)?;
+
+    Ok(css_file_path.to_string_lossy().into())
+}
+
+/// Writes a css file for a font family whose `@font-face` rules load the font files from
+/// google fonts, so nothing has to be downloaded
+pub(crate) fn write_remote_css_file_for_font(
+    font_files: &[(FontStyles, String)],
+    font_dir: &Path,
+    font_family_name: &str,
+    options: &CssOptions,
+) -> Result<String, String> {
+    let css_file_path = options.css_file_path(font_dir, font_family_name, "css");
+    let font_family_display_name = options.display_name(font_dir);
+
+    let mut font_files = font_files.to_vec();
+    font_files.sort_by(|(a, _), (b, _)| options.compare_font_styles(a, b));
+    let mut css: String = font_files
+        .iter()
+        .map(|(font_style, url)| {
+            let format = css_format(url_extension(url).unwrap_or("woff2"));
+            let sources = format!("url(\"{}\") format(\"{}\")", url, format);
+            let (_, font_weight) = font_style.get_style_and_weight();
+            font_face_rule_with_sources(
+                font_style,
+                font_weight,
+                &sources,
+                &font_family_display_name,
+                options,
+            ) + "\n"
+        })
+        .collect();
+    let font_styles: Vec<FontStyles> = font_files
+        .iter()
+        .map(|(font_style, _)| font_style.clone())
+        .collect();
+    css.push_str(&family_stack_rule(
+        font_family_name,
+        &font_family_display_name,
+        options,
+    ));
+    css.push_str(&utility_classes(
+        &font_styles,
+        font_family_name,
+        &font_family_display_name,
+        options,
+    ));
+
+    write_stylesheet(&css_file_path, &css, options)?;
+
+    Ok(css_file_path.to_string_lossy().into())
+}
+
+/// Writes a css file for a font family split into unicode subsets to the font directory.
+/// Creates an `@font-face` rule with a `unicode-range` for each subset of each font style,
+/// so browsers only download the subsets a page uses
+pub(crate) fn write_css2_file_for_font(
+    faces: &[Css2Face],
+    font_dir: &Path,
+    font_family_name: &str,
+    options: &CssOptions,
+) -> Result<String, String> {
+    let css_file_path = options.css_file_path(font_dir, font_family_name, "css");
+    let font_family_display_name = options.display_name(font_dir);
+    let url_prefix = font_url_prefix(font_dir, options)?;
+
+    // The sort is stable, so the subsets of a font style keep their order
+    let mut faces = faces.to_vec();
+    faces.sort_by(|a, b| options.compare_font_styles(&a.font_style, &b.font_style));
+    let mut css: String = faces
+        .iter()
+        .map(|face| {
+            let (font_style_name, font_weight) = face.font_style.get_style_and_weight();
+            let unicode_range = face
+                .unicode_range
+                .as_ref()
+                .map(|unicode_range| format!("\tunicode-range: {};\n", unicode_range))
+                .unwrap_or_default();
+            format!(
+                "/* {} */\n@font-face {{\n\tfont-family: {};\n\tsrc: url(\"{}{}\");\n\tfont-style: {};\n\tfont-weight: {};\n{}}}\n\n",
+                face.subset,
+                options.quote.quote(&font_family_display_name),
+                url_prefix,
+                face.file_name(
+                    options.filename_style.prefix(font_family_name),
+                    options.naming
+                ),
+                font_style_name,
+                font_weight,
+                unicode_range
+            )
+        })
+        .collect();
+    let mut font_styles: Vec<FontStyles> = vec![];
+    for face in &faces {
+        if !font_styles.contains(&face.font_style) {
+            font_styles.push(face.font_style.clone());
+        }
+    }
+    css.push_str(&family_stack_rule(
+        font_family_name,
+        &font_family_display_name,
+        options,
+    ));
+    css.push_str(&utility_classes(
+        &font_styles,
+        font_family_name,
+        &font_family_display_name,
+        options,
+    ));
+
+    write_stylesheet(&css_file_path, &css, options)?;
+
+    Ok(css_file_path.to_string_lossy().into())
+}
 
-        let font_face_string = format!(
-            "@font-face {{\n\tfont-family: \"{}\";\n\tsrc: url({});\n\tfont-style: {};\n\tfont-weight: {};\n}}\n",
+/// Writes a css module for a font family to the font directory, along with a typescript declaration file.
+/// Creates the same `@font-face` rules as `write_css_file_for_font` plus a scoped class for each font style,
+/// whose font stack ends with the fallback font families
+pub(crate) fn write_css_module_for_font(
+    font_styles: &[FontStyles],
+    font_dir: &Path,
+    font_family_name: &str,
+    options: &CssOptions,
+) -> Result<String, String> {
+    let css_file_path = options.css_file_path(font_dir, font_family_name, "module.css");
+    let dts_file_path = options.css_file_path(font_dir, font_family_name, "module.css.d.ts");
+    let font_family_display_name = options.display_name(font_dir);
+    let url_prefix = font_url_prefix(font_dir, options)?;
+
+    let mut css_module = String::new();
+    let mut declarations = String::from("declare const styles: {\n");
+
+    for (font_style, font_weight) in options.font_faces(font_styles) {
+        css_module.push_str(&font_face_rule(
+            &font_style,
+            font_weight,
+            &url_prefix,
+            font_family_name,
             &font_family_display_name,
-            format!("{:?}", font_dir.join(format!("{}-{}.woff2", font_family_name, font_style))),
+            options,
+        ));
+        css_module.push('\n');
+    }
+
+    for font_style in &options.sorted_font_styles(font_styles) {
+        let (font_style_name, font_weight) = font_style.get_style_and_weight();
+        let class_name = font_style.class_name(&options.weight_labels);
+
+        css_module.push_str(&format!(
+            ".{} {{\n\tfont-family: {}, {};\n\tfont-style: {};\n\tfont-weight: {};\n}}\n\n",
+            class_name,
+            options.quote.quote(&font_family_display_name),
+            options.fallback,
             font_style_name,
             font_weight
-        );
+        ));
+        declarations.push_str(&format!("\treadonly {}: string;\n", class_name));
+    }
 
-        let mut file = if idx == 0 {
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&css_file_path)
-        } else {
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(&css_file_path)
-        }
-        .map_err(|_| format!("Could not create file at path: {:?}", css_file_path))?;
-
-        if let Err(e) = writeln!(file, "{}", font_face_string) {
-            eprintln!(
-                "{}: Could not write to file: {:?}\n  {}: {}",
-                "error".red(),
-                &css_file_path,
-                "Caused by".red(),
-                e
+    declarations.push_str("};\nexport default styles;\n");
+
+    write_stylesheet(&css_file_path, &css_module, options)?;
+    write_generated_file(&dts_file_path, declarations)?;
+
+    Ok(css_file_path.to_string_lossy().into())
+}
+
+/// Manifest written next to the stylesheet with `--emit-json-and-css`, describing the same
+/// `@font-face` rules for build tools
+#[derive(Debug, Serialize)]
+struct FontManifest {
+    family: String,
+    fallback: String,
+    /// File name of the stylesheet the manifest describes
+    stylesheet: String,
+    faces: Vec<ManifestFace>,
+}
+
+/// An `@font-face` rule of the stylesheet
+#[derive(Debug, Serialize)]
+struct ManifestFace {
+    style: String,
+    weight: u16,
+    /// Url of the font file for each format, exactly as referenced in the stylesheet
+    files: BTreeMap<&'static str, String>,
+}
+
+/// Writes a json manifest for a font family next to its css file, built from the same font styles
+/// and options as `write_css_file_for_font` so the two never disagree
+pub(crate) fn write_json_manifest_for_font(
+    font_styles: &[FontStyles],
+    font_dir: &Path,
+    font_family_name: &str,
+    options: &CssOptions,
+) -> Result<String, String> {
+    let manifest_file_path = options.css_file_path(font_dir, font_family_name, "json");
+    let url_prefix = font_url_prefix(font_dir, options)?;
+    let extensions: Vec<&'static str> = match &options.src_order {
+        Some(src_order) => src_order
+            .iter()
+            .filter_map(|source| source.extension())
+            .collect(),
+        None => options.font_format.extensions().to_vec(),
+    };
+
+    let faces = options
+        .font_faces(font_styles)
+        .into_iter()
+        .map(|(font_style, font_weight)| {
+            let (font_style_name, _) = font_style.get_style_and_weight();
+            let files = extensions
+                .iter()
+                .map(|extension| {
+                    let file_path =
+                        options.font_file_path(&font_style, font_family_name, extension);
+                    (*extension, format!("{}{}", url_prefix, file_path))
+                })
+                .collect();
+            ManifestFace {
+                style: font_style_name.to_string(),
+                weight: font_weight,
+                files,
+            }
+        })
+        .collect();
+    let manifest = FontManifest {
+        family: options.display_name(font_dir),
+        fallback: options.fallback.clone(),
+        stylesheet: options
+            .css_file_path(font_dir, font_family_name, "css")
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string(),
+        faces,
+    };
+
+    let json = serde_json::to_string_pretty(&manifest)
+        .map_err(|_| "Could not serialize the font manifest".to_string())?;
+    write_generated_file(&manifest_file_path, json + "\n")?;
+
+    Ok(manifest_file_path.to_string_lossy().into())
+}
+
+/// Metrics of a font family written to `metrics.json` with `--metrics-json`
+#[derive(Debug, Serialize)]
+struct MetricsFile<'a> {
+    family: &'a str,
+    styles: Vec<StyleMetrics>,
+}
+
+/// Metrics of the font file of a font style, in font units
+#[derive(Debug, Serialize)]
+struct StyleMetrics {
+    style: &'static str,
+    weight: u16,
+    #[serde(flatten)]
+    metrics: FontMetrics,
+}
+
+/// Writes the metrics of the font file of each font style to `metrics.json` in the font directory,
+/// ordered by weight with the normal style before the italic one
+pub(crate) fn write_metrics_json_for_font(
+    font_styles: &[FontStyles],
+    font_dir: &Path,
+    font_family: &str,
+    metrics: &HashMap<FontStyles, FontMetrics>,
+) -> Result<String, String> {
+    let metrics_file_path = font_dir.join("metrics.json");
+    let mut styles: Vec<StyleMetrics> = font_styles
+        .iter()
+        .filter_map(|font_style| {
+            let (style, weight) = font_style.get_style_and_weight();
+            Some(StyleMetrics {
+                style,
+                weight,
+                metrics: *metrics.get(font_style)?,
+            })
+        })
+        .collect();
+    styles.sort_by_key(|style_metrics| (style_metrics.weight, style_metrics.style == "italic"));
+
+    let json = serde_json::to_string_pretty(&MetricsFile {
+        family: font_family,
+        styles,
+    })
+    .map_err(|_| "Could not serialize the font metrics".to_string())?;
+    write_generated_file(&metrics_file_path, json + "\n")?;
+
+    Ok(metrics_file_path.to_string_lossy().into())
+}
+
+/// Color themes for the html preview of a font family
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum PreviewTheme {
+    /// Dark text on a light background
+    #[default]
+    Light,
+    /// Light text on a dark background
+    Dark,
+}
+
+impl PreviewTheme {
+    /// Background and foreground colors of the theme
+    fn colors(&self) -> (&'static str, &'static str) {
+        match self {
+            PreviewTheme::Light => ("#ffffff", "#111111"),
+            PreviewTheme::Dark => ("#111111", "#f5f5f5"),
+        }
+    }
+}
+
+/// Writes a `preview.html` specimen page for a font family to the font directory.
+/// The page loads the fonts from the font directory itself, so it works regardless of where the stylesheets are
+pub(crate) fn write_preview_for_font(
+    font_styles: &[FontStyles],
+    font_dir: &Path,
+    font_family_name: &str,
+    options: &CssOptions,
+    theme: PreviewTheme,
+) -> Result<String, String> {
+    let preview_file_path = font_dir.join("preview.html");
+    let font_family_display_name = options.display_name(font_dir);
+    let (background, foreground) = theme.colors();
+    let font_styles = options.sorted_font_styles(font_styles);
+
+    let font_faces: String = font_styles
+        .iter()
+        .map(|font_style| {
+            font_face_rule(
+                font_style,
+                font_style.get_style_and_weight().1,
+                "",
+                font_family_name,
+                &font_family_display_name,
+                options,
             )
+        })
+        .collect();
+
+    let specimens: String = font_styles
+        .iter()
+        .map(|font_style| {
+            let (font_style_name, font_weight) = font_style.get_style_and_weight();
+            format!(
+                "\t<div class=\"specimen\">\n\t\t<div class=\"label\">{} ({} {})</div>\n\t\t<div class=\"sample\" style=\"font-style: {}; font-weight: {};\">The quick brown fox jumps over the lazy dog</div>\n\t</div>\n",
+                font_style, font_weight, font_style_name, font_style_name, font_weight
+            )
+        })
+        .collect();
+
+    let preview = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{family}</title>\n<style>\n{font_faces}body {{\n\tbackground: {background};\n\tcolor: {foreground};\n\tfont-family: \"{family}\", {fallback};\n\tmargin: 2rem;\n}}\n.specimen {{\n\tmargin-bottom: 1.5rem;\n}}\n.label {{\n\tfont-family: monospace;\n\tfont-size: 0.8rem;\n\topacity: 0.6;\n}}\n.sample {{\n\tfont-size: 2rem;\n}}\n</style>\n</head>\n<body>\n\t<h1>{family}</h1>\n{specimens}</body>\n</html>\n",
+        family = font_family_display_name,
+        font_faces = font_faces,
+        background = background,
+        foreground = foreground,
+        fallback = options.fallback,
+        specimens = specimens,
+    );
+
+    write_generated_file(&preview_file_path, preview)?;
+
+    Ok(preview_file_path.to_string_lossy().into())
+}
+
+/// Writes an `index.css` file that imports the stylesheet of every font family, so a project only
+/// needs to reference a single stylesheet. The file is written to the css directory when the stylesheets
+/// are kept there, otherwise to the output directory importing the `fonts.css` of each font directory
+pub(crate) fn write_index_css(output_dir: &Path, css_dir: Option<&Path>) -> Result<String, String> {
+    let index_dir = css_dir.unwrap_or(output_dir);
+    let index_file_path = index_dir.join("index.css");
+    let entries = std::fs::read_dir(index_dir)
+        .map_err(|_| format!("Could not read directory: {}", index_dir.to_string_lossy()))?;
+
+    let mut stylesheets: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            match css_dir {
+                Some(_) => (file_name.ends_with(".css")
+                    && !file_name.ends_with(".module.css")
+                    && file_name != "index.css")
+                    .then_some(file_name),
+                None => entry
+                    .path()
+                    .join("fonts.css")
+                    .is_file()
+                    .then(|| format!("{}/fonts.css", file_name)),
+            }
+        })
+        .collect();
+    stylesheets.sort();
+
+    let imports: String = stylesheets
+        .iter()
+        .map(|stylesheet| format!("@import url('{}');\n", stylesheet))
+        .collect();
+
+    std::fs::write(&index_file_path, imports)
+        .map_err(|_| format!("Could not create file at path: {:?}", index_file_path))?;
+
+    Ok(index_file_path.to_string_lossy().into())
+}
+
+/// Writes an `index.html` page to the output directory linking the preview page and stylesheet of every
+/// font family that has a preview, so all the installed fonts can be reviewed from a single page
+pub(crate) fn write_preview_index(
+    output_dir: &Path,
+    css_dir: Option<&Path>,
+) -> Result<String, String> {
+    let index_file_path = output_dir.join("index.html");
+    let entries = std::fs::read_dir(output_dir)
+        .map_err(|_| format!("Could not read directory: {}", output_dir.to_string_lossy()))?;
+
+    let mut font_dir_names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("preview.html").is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    font_dir_names.sort();
+
+    let mut links = String::new();
+    for font_dir_name in &font_dir_names {
+        let stylesheet = match css_dir {
+            Some(css_dir) => {
+                let css_file_path = css_dir.join(format!("{}.css", font_dir_name));
+                css_file_path
+                    .is_file()
+                    .then(|| relative_path(output_dir, &css_file_path))
+                    .transpose()?
+                    .map(|path| path.to_string_lossy().replace('\\', "/"))
+            }
+            None => ["fonts.css", "fonts.module.css"]
+                .iter()
+                .find(|file_name| output_dir.join(font_dir_name).join(file_name).is_file())
+                .map(|file_name| format!("{}/{}", font_dir_name, file_name)),
+        };
+
+        links.push_str(&format!(
+            "\t\t<li><a href=\"{dir}/preview.html\">{family}</a>",
+            dir = font_dir_name,
+            family = format_font_string(font_dir_name)
+        ));
+        if let Some(stylesheet) = stylesheet {
+            links.push_str(&format!(
+                " <a class=\"stylesheet\" href=\"{}\">css</a>",
+                stylesheet
+            ));
         }
+        links.push_str("</li>\n");
     }
 
-    Ok(css_file_path.to_string_lossy().into())
+    let index = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Fonts</title>\n<style>\nbody {{\n\tfont-family: sans-serif;\n\tmargin: 2rem;\n}}\nli {{\n\tmargin-bottom: 0.5rem;\n}}\n.stylesheet {{\n\tfont-family: monospace;\n\tfont-size: 0.8rem;\n\topacity: 0.6;\n}}\n</style>\n</head>\n<body>\n\t<h1>Fonts</h1>\n\t<ul>\n{}\t</ul>\n</body>\n</html>\n",
+        links
+    );
+
+    std::fs::write(&index_file_path, index)
+        .map_err(|_| format!("Could not create file at path: {:?}", index_file_path))?;
+
+    Ok(index_file_path.to_string_lossy().into())
 }
 
-/// Converts a ttf font file to a woff2 font file using the `woff2_compress` tool.
-/// Uses the `get_woff2_compress` function to get the path to the `woff2_compress` binary or returns an error
-pub fn convert_to_woff2(ttf_path: &PathBuf) -> Result<(), String> {
-    let woff2_compress = match get_woff2_compress() {
-        Ok(path) => path,
-        Err(e) => return Err(e),
+/// Checks that the declaration ending at a `;` or `}` is a `property: value` pair, e.g. `font-weight: 700`
+fn check_declaration(declaration: &str, line: usize) -> Result<(), String> {
+    let declaration = declaration.trim();
+    if declaration.is_empty() {
+        return Ok(());
+    }
+    let Some((property, value)) = declaration.split_once(':') else {
+        return Err(format!(
+            "line {}: `{}` is not a declaration",
+            line, declaration
+        ));
     };
+    let property = property.trim();
+    if property.is_empty()
+        || !property
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "line {}: `{}` is not a property name",
+            line, property
+        ));
+    }
+    if value.trim().is_empty() {
+        return Err(format!("line {}: `{}` has no value", line, property));
+    }
+    Ok(())
+}
+
+/// Checks that a stylesheet is syntactically valid css: comments and strings are closed, brackets are
+/// balanced and every declaration in a rule is a `property: value` pair. Returns the first problem with its line
+pub(crate) fn validate_css(css: &str) -> Result<(), String> {
+    let mut open_brackets: Vec<(char, usize)> = vec![];
+    let mut declaration = String::new();
+    let mut line = 1;
+    let mut chars = css.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '/' if chars.peek() == Some(&'*') => {
+                let start_line = line;
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => {
+                            return Err(format!("line {}: comment is never closed", start_line))
+                        }
+                    }
+                }
+                continue;
+            }
+            '"' | '\'' => {
+                declaration.push(c);
+                loop {
+                    match chars.next() {
+                        Some('\\') => {
+                            declaration.push('\\');
+                            declaration.extend(chars.next());
+                        }
+                        Some(quote) if quote == c => break,
+                        Some('\n') | None => {
+                            return Err(format!("line {}: string is never closed", line))
+                        }
+                        Some(c) => declaration.push(c),
+                    }
+                }
+            }
+            '(' | '[' => open_brackets.push((c, line)),
+            '{' => {
+                open_brackets.push((c, line));
+                // The text before a block is a selector or an at-rule, not a declaration
+                declaration.clear();
+                continue;
+            }
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                match open_brackets.pop() {
+                    Some((open, _)) if open == expected => {}
+                    _ => return Err(format!("line {}: unexpected `{}`", line, c)),
+                }
+                if c == '}' {
+                    check_declaration(&declaration, line)?;
+                    declaration.clear();
+                    continue;
+                }
+            }
+            ';' if open_brackets.last().is_some_and(|(open, _)| *open == '{') => {
+                check_declaration(&declaration, line)?;
+                declaration.clear();
+                continue;
+            }
+            ';' if open_brackets.is_empty() => {
+                declaration.clear();
+                continue;
+            }
+            _ => {}
+        }
+        declaration.push(c);
+    }
+
+    match open_brackets.last() {
+        Some((open, open_line)) => Err(format!("line {}: `{}` is never closed", open_line, open)),
+        None => Ok(()),
+    }
+}
+
+/// Parses the font styles used by the `@font-face` rules of a font family in an existing stylesheet.
+/// Rules without a `font-weight` or `font-style` get the css defaults of `400` and `normal`
+pub(crate) fn parse_css_font_styles(
+    css: &str,
+    font_family: &str,
+) -> Result<Vec<FontStyles>, String> {
+    let mut css_without_comments = String::new();
+    let mut rest = css;
+    while let Some(comment_start) = rest.find("/*") {
+        css_without_comments.push_str(&rest[..comment_start]);
+        rest = rest[comment_start..]
+            .find("*/")
+            .map_or("", |comment_end| &rest[comment_start + comment_end + 2..]);
+    }
+    css_without_comments.push_str(rest);
+
+    let mut font_styles = vec![];
+    for rule in css_without_comments.split("@font-face").skip(1) {
+        let Some(block) = rule
+            .split_once('{')
+            .and_then(|(_, block)| block.split_once('}'))
+            .map(|(block, _)| block)
+        else {
+            continue;
+        };
+
+        let mut rule_font_family = None;
+        let mut font_weight = "400".to_string();
+        let mut italic = false;
+        for declaration in block.split(';') {
+            let Some((property, value)) = declaration.split_once(':') else {
+                continue;
+            };
+            let value = value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_lowercase();
+            match property.trim().to_lowercase().as_str() {
+                "font-family" => rule_font_family = Some(value),
+                "font-weight" => font_weight = value,
+                "font-style" => italic = value == "italic" || value.starts_with("oblique"),
+                _ => {}
+            }
+        }
+
+        if rule_font_family.as_deref() != Some(font_family.to_lowercase().as_str()) {
+            continue;
+        }
+
+        let numeric_weight = match font_weight.as_str() {
+            "normal" => 400,
+            "bold" => 700,
+            font_weight => font_weight
+                .parse::<u16>()
+                .map_err(|_| format!("Unsupported font-weight `{}` in stylesheet", font_weight))?,
+        };
+        let font_style = FontStyles::from_weight(numeric_weight, italic)
+            .ok_or_else(|| format!("Unsupported font-weight `{}` in stylesheet", font_weight))?;
+
+        if !font_styles.contains(&font_style) {
+            font_styles.push(font_style);
+        }
+    }
+
+    if font_styles.is_empty() {
+        return Err(format!(
+            "Could not find any @font-face rules for {} in the stylesheet",
+            font_family
+        ));
+    }
+
+    Ok(font_styles)
+}
+
+/// Finds the font styles of the font files with an extension in a font directory from their file names,
+/// e.g. `inter-bold-italic.woff2`, `inter-700-italic.woff2` or `bold-italic.woff2`, along with the naming
+/// scheme and filename style the files use. Files whose style can't be parsed are skipped
+pub(crate) fn scan_font_styles(
+    font_dir: &Path,
+    font_family_name: &str,
+    extension: &str,
+) -> Result<(Vec<FontStyles>, FileNaming, FilenameStyle), String> {
+    let entries = std::fs::read_dir(font_dir)
+        .map_err(|_| format!("Could not read directory: {}", font_dir.to_string_lossy()))?;
+    let prefix = format!("{}-", font_family_name);
+    let extension = format!(".{}", extension);
+
+    let mut naming = FileNaming::Named;
+    let mut filename_style = FilenameStyle::WithFamily;
+    let mut font_styles: Vec<FontStyles> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let file_stem = file_name.strip_suffix(&extension)?;
+            let (suffix, font_style) = match file_stem.strip_prefix(&prefix) {
+                Some(suffix) => (suffix, FontStyles::from_filename_suffix(suffix)?),
+                None => {
+                    let font_style = FontStyles::from_filename_suffix(file_stem)?;
+                    filename_style = FilenameStyle::StyleOnly;
+                    (file_stem, font_style)
+                }
+            };
+            if suffix == font_style.file_suffix(FileNaming::Numeric) {
+                naming = FileNaming::Numeric;
+            }
+            Some(font_style)
+        })
+        .collect();
+    font_styles.sort_by_key(|font_style| {
+        let (font_style_name, font_weight) = font_style.get_style_and_weight();
+        (font_weight, font_style_name)
+    });
+
+    Ok((font_styles, naming, filename_style))
+}
+
+/// Removes the font files in the font directory that are not one of the wanted files,
+/// after confirming which files are removed. Returns the paths of the removed files
+pub(crate) fn prune_font_files(
+    wanted_files: &[String],
+    font_dir: &Path,
+    interaction: Interaction,
+) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(font_dir)
+        .map_err(|_| format!("Could not read directory: {}", font_dir.to_string_lossy()))?;
+
+    let mut pruned_files = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_font_file = path.extension().is_some_and(|extension| {
+            ["woff2", "ttf", "otf"]
+                .iter()
+                .any(|font| extension == *font)
+        });
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if is_font_file && !wanted_files.contains(&file_name) {
+            pruned_files.push(path);
+        }
+    }
+    pruned_files.sort();
+
+    if pruned_files.is_empty() {
+        return Ok(pruned_files);
+    }
+    let file_names: Vec<String> = pruned_files
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    if !confirm(
+        &format!(
+            "Remove {} font files from {}? ({})",
+            pruned_files.len(),
+            font_dir.to_string_lossy(),
+            file_names.join(", ")
+        ),
+        interaction,
+    )? {
+        return Ok(vec![]);
+    }
+
+    for path in &pruned_files {
+        std::fs::remove_file(path)
+            .map_err(|_| format!("Could not delete file: {}", path.to_string_lossy()))?;
+    }
+
+    Ok(pruned_files)
+}
+
+/// How prompts that need the user's confirmation are answered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Interaction {
+    /// Ask on the terminal
+    Prompt,
+    /// Answer yes without asking, `--yes`
+    AssumeYes,
+    /// Fail instead of asking, `--no-interactive`
+    NoInteractive,
+}
+
+/// Asks the user a yes or no question, defaulting to no.
+/// Fails when prompting is disabled or there is no terminal to prompt on, so scripts never hang waiting for input
+pub(crate) fn confirm(question: &str, interaction: Interaction) -> Result<bool, String> {
+    match interaction {
+        Interaction::AssumeYes => return Ok(true),
+        Interaction::NoInteractive => {
+            return Err(format!(
+                "{} needs confirmation, pass --yes to confirm it",
+                question
+            ))
+        }
+        Interaction::Prompt if !std::io::stdin().is_terminal() => {
+            return Err(format!(
+            "{} needs confirmation but there is no terminal to prompt on, pass --yes to confirm it",
+            question
+        ))
+        }
+        Interaction::Prompt => {}
+    }
+
+    eprint!("{} {} ", question, "[y/N]".dimmed());
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|_| "Could not read the answer to the prompt".to_string())?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Sends a GET request, retrying network errors and server errors up to `retries` times with an
+/// exponential backoff starting at half a second. Client errors, e.g. a missing font, are returned right away
+pub(crate) async fn get_with_retries(
+    client: &Client,
+    url: &str,
+    retries: u32,
+) -> Result<Response, reqwest::Error> {
+    send_with_retries(client.get(url), retries).await
+}
+
+/// Sends a request the same way as `send_with_retries` and reads the body of the response as text.
+/// The request is recorded in the audit log
+pub(crate) async fn fetch_text_with_retries(
+    request: RequestBuilder,
+    url: &str,
+    retries: u32,
+) -> Result<(StatusCode, String), reqwest::Error> {
+    let started = Instant::now();
+    let result = async {
+        let response = send_with_retries(request, retries).await?;
+        let status = response.status();
+        record_quota_headers(response.headers());
+        Ok::<_, reqwest::Error>((status, response.text().await?))
+    }
+    .await;
+
+    match &result {
+        Ok((status, body)) => record_request(
+            "GET",
+            url,
+            Some(*status),
+            body.len() as u64,
+            started.elapsed(),
+        ),
+        Err(err) => record_request("GET", url, err.status(), 0, started.elapsed()),
+    }
+    result
+}
+
+/// Redirect policy following at most `max_redirects` redirects, which fails with a clear error
+/// when the limit is hit or a redirect leads back to a url that was already visited
+pub(crate) fn redirect_policy(max_redirects: usize) -> Policy {
+    Policy::custom(move |attempt| {
+        if attempt.previous().contains(attempt.url()) {
+            let message = format!("redirect loop detected at {}", attempt.url());
+            attempt.error(message)
+        } else if attempt.previous().len() > max_redirects {
+            let message = format!(
+                "gave up after {} redirects, pass --max-redirects to follow more",
+                max_redirects
+            );
+            attempt.error(message)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Sends a request without a streaming body the same way as `get_with_retries`, for requests that need headers
+pub(crate) async fn send_with_retries(
+    request: RequestBuilder,
+    retries: u32,
+) -> Result<Response, reqwest::Error> {
+    let mut attempt = 0;
+    loop {
+        let result = request
+            .try_clone()
+            .expect("requests without a streaming body can be cloned")
+            .send()
+            .await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(err) => !err.is_builder() && !err.is_redirect(),
+        };
+        if !retryable || attempt >= retries {
+            return result;
+        }
+
+        tokio::time::sleep(Duration::from_millis(500 * 2u64.pow(attempt.min(6)))).await;
+        attempt += 1;
+    }
+}
+
+/// Converts a ttf font file to a woff2 font file using the `woff2_compress` tool.
+/// Uses the `get_woff2_compress` function to get the path to the `woff2_compress` binary or returns an error.
+/// The ttf file is deleted after the conversion unless `keep_ttf` is set, and `woff2_compress` is killed
+/// when it runs longer than `timeout`
+pub fn convert_to_woff2(ttf_path: &Path, keep_ttf: bool, timeout: Duration) -> Result<(), String> {
+    let woff2_compress = get_woff2_compress()?;
     let mut process = Popen::create(
-        &[woff2_compress, ttf_path.clone()],
+        &[woff2_compress, ttf_path.to_path_buf()],
         PopenConfig {
             stdout: Redirection::Pipe,
             stderr: Redirection::Pipe,
@@ -109,37 +1504,513 @@ pub fn convert_to_woff2(ttf_path: &PathBuf) -> Result<(), String> {
     )
     .map_err(|_| "Failed to start woff2_compress".to_string())?;
 
-    let status = process
-        .wait()
-        .map_err(|_| "Failed to wait for woff2_compress process".to_string())?;
+    // Reading the output as it is written keeps woff2_compress from blocking on a full pipe
+    let started = Instant::now();
+    let output = process
+        .communicate_start(None)
+        .limit_time(timeout)
+        .read_string();
+    let status = match &output {
+        Ok(_) => process.wait_timeout(timeout.saturating_sub(started.elapsed())),
+        Err(err) if err.kind() == std::io::ErrorKind::TimedOut => Ok(None),
+        Err(_) => return Err("Failed to read the output of woff2_compress".to_string()),
+    };
+    let status = match status {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            process.kill().ok();
+            process.wait().ok();
+            std::fs::remove_file(ttf_path.with_extension("woff2")).ok();
+            return Err(format!(
+                "woff2_compress timed out after {}s converting {}",
+                timeout.as_secs(),
+                ttf_path.to_string_lossy()
+            ));
+        }
+        Err(_) => return Err("Failed to wait for woff2_compress process".to_string()),
+    };
 
     if !status.success() {
-        return Err(format!("woff2_compress failed with status: {:?}", status));
+        let stderr = output
+            .ok()
+            .and_then(|(_, stderr)| stderr)
+            .map(|stderr| stderr.trim().to_string())
+            .unwrap_or_default();
+        let mut message = format!(
+            "woff2_compress failed with status {:?} converting {}",
+            status,
+            ttf_path.to_string_lossy()
+        );
+        if !stderr.is_empty() {
+            message.push_str(&format!(": {}", stderr));
+        }
+        return Err(message);
     }
 
-    std::fs::remove_file(ttf_path)
-        .map_err(|_| format!("Could not delete file: {}", ttf_path.to_string_lossy()))?;
+    if !keep_ttf {
+        std::fs::remove_file(ttf_path)
+            .map_err(|_| format!("Could not delete file: {}", ttf_path.to_string_lossy()))?;
+    }
 
     Ok(())
 }
 
+/// Checks that a converted font file is a woff2 file by reading its `wOF2` signature.
+/// `woff2_compress` can exit successfully without producing a usable file, so this catches that early
+pub fn verify_woff2(woff2_path: &Path) -> Result<(), String> {
+    let contents = std::fs::read(woff2_path)
+        .map_err(|_| format!("Could not read file: {}", woff2_path.to_string_lossy()))?;
+
+    if contents.is_empty() {
+        return Err(format!(
+            "Converted file is empty: {}",
+            woff2_path.to_string_lossy()
+        ));
+    }
+    if !contents.starts_with(b"wOF2") {
+        return Err(format!(
+            "Converted file is not a woff2 file: {}",
+            woff2_path.to_string_lossy()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks whether an io error means the disk ran out of space
+pub fn is_disk_full(err: &std::io::Error) -> bool {
+    err.kind() == std::io::ErrorKind::StorageFull
+}
+
+/// Error message for a file that could not be written because the disk is full
+pub fn disk_full_message(path: &Path) -> String {
+    format!(
+        "Disk full while writing {}, aborting. Free up some space and try again",
+        path.to_string_lossy()
+    )
+}
+
+/// Error printed to stderr as a single line of json with `--json-errors`
+#[derive(Debug, Serialize)]
+struct JsonError<'a> {
+    /// Kind of failure scripts can match on, e.g. `install_failed`
+    code: &'a str,
+    message: &'a str,
+    /// What the error is about, e.g. the font family that failed to install
+    context: BTreeMap<&'a str, &'a str>,
+}
+
+/// Prints an error to stderr as a single line of json, for `--json-errors`
+pub fn print_json_error(code: &str, message: &str, context: &[(&str, &str)]) {
+    let error = JsonError {
+        code,
+        message,
+        context: context.iter().copied().collect(),
+    };
+    if let Ok(json) = serde_json::to_string(&error) {
+        eprintln!("{}", json);
+    }
+}
+
+/// Formats a finished SHA-256 hash as lowercase hex
+pub fn sha256_hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Computes the SHA-256 checksum of a file on disk
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let contents = std::fs::read(path)
+        .map_err(|_| format!("Could not read file: {}", path.to_string_lossy()))?;
+
+    Ok(sha256_hex(Sha256::new_with_prefix(contents)))
+}
+
 /// Gets the API key from the environment variable `GFONT_API_KEY` or the CLI argument `--api-key`
-pub fn get_api_key(cli_api_key: Option<String>) -> String {
+pub fn get_api_key(cli_api_key: Option<String>) -> Result<String, GfontError> {
     cli_api_key
         .or_else(|| env::var("GFONT_API_KEY").ok().filter(|key| !key.is_empty()))
-        .unwrap_or_else(|| {
-            eprintln!(
-                "{}: Using gfontapi requires an API key.\
-                \n  {}\n    - export GFONT_API_KEY={}\n    - gfontapi --api-key={}",
-                "error".red(),
-                "Pass it to the program in one of the following ways".dimmed(),
-                "<YOUR_API_KEY>".cyan(),
-                "<YOUR_API_KEY>".cyan()
-            );
-            process::exit(1);
-        })
+        .ok_or(GfontError::MissingApiKey)
+}
+
+/// Url and key the developer API is called with
+#[derive(Debug, Clone)]
+pub(crate) struct ApiConfig {
+    /// Url of the webfonts endpoint, only changed to point the tests at a mock server
+    pub base_url: String,
+    pub key: String,
+    /// Fetch a font family once more when its response can't be parsed
+    pub refetch_on_parse_error: bool,
+    /// Report when a response had to be fetched again
+    pub verbose: bool,
+}
+
+/// Gets the gfontapi directory in the users home directory, `~/.gfontapi`
+pub fn get_gfontapi_dir() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".gfontapi")
+}
+
+/// Reads the font names listed in a file, one per line.
+/// Blank lines and lines starting with `#` are skipped
+pub fn read_font_list(path: &Path) -> Result<Vec<String>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|_| format!("Could not read font list: {}", path.to_string_lossy()))?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads a mapping of font names to the directory and file names they are installed under,
+/// one `Font Name -> output-name` pair per line. Blank lines and lines starting with `#` are skipped
+pub fn read_family_file_map(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|_| format!("Could not read family file map: {}", path.to_string_lossy()))?;
+
+    let mut family_file_map: Vec<(String, String)> = vec![];
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((fontname, output_name)) = line.split_once("->") else {
+            return Err(format!(
+                "Line {} of the family file map is not a `Font Name -> output-name` pair",
+                idx + 1
+            ));
+        };
+        let (fontname, output_name) = (fontname.trim(), output_name.trim());
+        if fontname.is_empty() || !is_valid_output_name(output_name) {
+            return Err(format!(
+                "Line {} of the family file map has an invalid font or output name",
+                idx + 1
+            ));
+        }
+        if family_file_map
+            .iter()
+            .any(|(mapped, _)| mapped.eq_ignore_ascii_case(fontname))
+        {
+            return Err(format!(
+                "`{}` is mapped more than once in the family file map",
+                fontname
+            ));
+        }
+
+        family_file_map.push((fontname.to_string(), output_name.to_string()));
+    }
+
+    Ok(family_file_map)
+}
+
+/// Whether a name can be used for a font directory and its files, a single path component
+fn is_valid_output_name(output_name: &str) -> bool {
+    !output_name.is_empty()
+        && !output_name.contains(['/', '\\'])
+        && output_name != "."
+        && output_name != ".."
+}
+
+/// Names a font family is installed under, from `--family-file-map` or `--family-name-map`
+#[derive(Debug, Clone)]
+pub struct MappedFamily {
+    /// Name used in the `font-family` of the generated css, only set by the family name map
+    pub display_name: Option<String>,
+    /// Name of the font directory and files
+    pub output_name: String,
+}
+
+/// Reads a mapping of font names to the name used in the `font-family` of the generated css and the
+/// directory and file names they are installed under, one `Font Name -> Display Name -> output-name`
+/// entry per line. Blank lines and lines starting with `#` are skipped
+pub fn read_family_name_map(path: &Path) -> Result<Vec<(String, MappedFamily)>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|_| format!("Could not read family name map: {}", path.to_string_lossy()))?;
+
+    let mut family_name_map: Vec<(String, MappedFamily)> = vec![];
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split("->").map(str::trim).collect();
+        let [fontname, display_name, output_name] = fields[..] else {
+            return Err(format!(
+                "Line {} of the family name map is not a `Font Name -> Display Name -> output-name` entry",
+                idx + 1
+            ));
+        };
+        if fontname.is_empty() || display_name.is_empty() || !is_valid_output_name(output_name) {
+            return Err(format!(
+                "Line {} of the family name map has an invalid font, display or output name",
+                idx + 1
+            ));
+        }
+        if family_name_map
+            .iter()
+            .any(|(mapped, _)| mapped.eq_ignore_ascii_case(fontname))
+        {
+            return Err(format!(
+                "`{}` is mapped more than once in the family name map",
+                fontname
+            ));
+        }
+
+        family_name_map.push((
+            fontname.to_string(),
+            MappedFamily {
+                display_name: Some(display_name.to_string()),
+                output_name: output_name.to_string(),
+            },
+        ));
+    }
+
+    Ok(family_name_map)
+}
+
+/// Finds a directory in the output directory whose name only differs from `dir_name` by case.
+/// Case insensitive filesystems would treat both as the same directory, and case sensitive ones would
+/// end up with two copies of the same font
+pub(crate) fn find_dir_differing_by_case(output_dir: &Path, dir_name: &str) -> Option<String> {
+    std::fs::read_dir(output_dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .find(|existing| existing != dir_name && existing.eq_ignore_ascii_case(dir_name))
+}
+
+/// Gets the target directory, `./fonts` unless `--target-dir` is passed. Fails when the target directory
+/// or one of its parents is a file, which would otherwise only surface as an io error once it's created
+pub fn get_output_dir(target_dir: Option<PathBuf>) -> Result<PathBuf, String> {
+    let output_dir = target_dir.unwrap_or_else(|| PathBuf::from("./fonts"));
+    ensure_dir_path(&output_dir)?;
+    Ok(output_dir)
+}
+
+/// Checks that a directory can be created at a path, i.e. neither the path nor one of its parents is a file
+pub(crate) fn ensure_dir_path(path: &Path) -> Result<(), String> {
+    for ancestor in path.ancestors() {
+        if ancestor.is_dir() {
+            break;
+        }
+        if ancestor.exists() {
+            return Err(format!(
+                "Target path {} is a file, not a directory",
+                ancestor.to_string_lossy()
+            ));
+        }
+    }
+    Ok(())
 }
 
-pub fn get_output_dir(target_dir: Option<PathBuf>) -> PathBuf {
-    target_dir.unwrap_or_else(|| PathBuf::from("./fonts"))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_css_font_styles_handles_whitespace_and_ordering() {
+        let css = r#"
+            /* Inter */
+            @font-face{font-family:'Inter';font-weight:700;src:url(inter-bold.woff2)}
+            @font-face {
+                src: url("inter-italic.woff2");
+                font-style :  italic ;
+                FONT-FAMILY: "Inter"
+            }
+            @font-face { font-weight: bold; font-family: Inter; font-style: italic; }
+            @font-face { font-family: "Lato"; font-weight: 300; }
+        "#;
+
+        assert_eq!(
+            parse_css_font_styles(css, "Inter").unwrap(),
+            vec![
+                FontStyles::Bold,
+                FontStyles::RegularItalic,
+                FontStyles::BoldItalic
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_css_font_styles_errors_without_matching_rules() {
+        let css = "@font-face { font-family: 'Lato'; font-weight: 300; }";
+
+        assert!(parse_css_font_styles(css, "Inter").is_err());
+    }
+
+    #[test]
+    fn sorted_font_styles_follows_the_css_order() {
+        let font_styles = [
+            FontStyles::BoldItalic,
+            FontStyles::Regular,
+            FontStyles::Bold,
+            FontStyles::Black,
+        ];
+
+        let by_weight = CssOptions::default().sorted_font_styles(&font_styles);
+        assert_eq!(
+            by_weight,
+            vec![
+                FontStyles::Regular,
+                FontStyles::Bold,
+                FontStyles::BoldItalic,
+                FontStyles::Black
+            ]
+        );
+
+        let alphabetical = CssOptions {
+            order: CssOrder::Alpha,
+            ..Default::default()
+        }
+        .sorted_font_styles(&font_styles);
+        assert_eq!(
+            alphabetical,
+            vec![
+                FontStyles::Black,
+                FontStyles::Bold,
+                FontStyles::BoldItalic,
+                FontStyles::Regular
+            ]
+        );
+    }
+
+    #[test]
+    fn ensure_dir_path_rejects_a_file_in_the_path() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file_path = dir.path().join("fonts");
+        std::fs::write(&file_path, "").unwrap();
+
+        assert!(ensure_dir_path(&dir.path().join("new/fonts")).is_ok());
+        assert!(ensure_dir_path(&file_path)
+            .unwrap_err()
+            .ends_with("fonts is a file, not a directory"));
+        assert!(ensure_dir_path(&file_path.join("inter")).is_err());
+    }
+
+    #[test]
+    fn css_quote_auto_only_quotes_names_that_need_it() {
+        assert_eq!(CssQuote::Auto.quote("Open Sans"), "Open Sans");
+        assert_eq!(CssQuote::Auto.quote("Press Start 2P"), "\"Press Start 2P\"");
+        assert_eq!(CssQuote::Auto.quote("Serif"), "\"Serif\"");
+        assert_eq!(CssQuote::Single.quote("Inter"), "'Inter'");
+        assert_eq!(CssQuote::Double.quote("Inter"), "\"Inter\"");
+    }
+
+    #[test]
+    fn write_stylesheet_leaves_an_unchanged_stylesheet_alone() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let css_file_path = dir.path().join("fonts.css");
+        let options = CssOptions {
+            line_ending: LineEnding::Lf,
+            skip_if_unchanged: true,
+            ..Default::default()
+        };
+        let last_write = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(86_400);
+        let modified = || {
+            std::fs::metadata(&css_file_path)
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+
+        write_stylesheet(&css_file_path, ".a {}\n", &options).unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&css_file_path)
+            .unwrap();
+        file.set_modified(last_write).unwrap();
+        write_stylesheet(&css_file_path, ".a {}\n", &options).unwrap();
+        assert_eq!(modified(), last_write);
+
+        write_stylesheet(&css_file_path, ".b {}\n", &options).unwrap();
+        assert_ne!(modified(), last_write);
+        assert_eq!(std::fs::read_to_string(&css_file_path).unwrap(), ".b {}\n");
+    }
+
+    #[test]
+    fn validate_css_reports_the_line_of_the_first_problem() {
+        let options = CssOptions {
+            font_format: FontFormat::Both,
+            utility_classes: true,
+            family_stack: true,
+            fallback: "system-ui, \"Segoe UI\", sans-serif".to_string(),
+            ..Default::default()
+        };
+        let css = font_face_rule(&FontStyles::Bold, 700, "./", "inter", "Inter", &options)
+            + &family_stack_rule("inter", "Inter", &options)
+            + &utility_classes(&[FontStyles::Bold], "inter", "Inter", &options);
+        assert_eq!(validate_css(&css), Ok(()));
+
+        assert_eq!(
+            validate_css("@font-face {\n\tsrc: url(\"./inter.woff2);\n}\n"),
+            Err("line 2: string is never closed".to_string())
+        );
+        assert_eq!(
+            validate_css(".a {\n\tfont-weight 700;\n}\n"),
+            Err("line 2: `font-weight 700` is not a declaration".to_string())
+        );
+        assert_eq!(
+            validate_css("/* a */\n.a {\n\tfont-weight: 700;\n"),
+            Err("line 2: `{` is never closed".to_string())
+        );
+    }
+
+    #[test]
+    fn line_ending_crlf_does_not_double_carriage_returns() {
+        assert_eq!(
+            LineEnding::Crlf.apply("@font-face {\n\tfont-weight: 400;\r\n}\n"),
+            "@font-face {\r\n\tfont-weight: 400;\r\n}\r\n"
+        );
+        assert_eq!(LineEnding::Lf.apply("a\nb\n"), "a\nb\n");
+    }
+
+    #[test]
+    fn font_face_rule_lists_sources_in_the_src_order() {
+        let options = CssOptions {
+            font_format: FontFormat::Both,
+            src_order: Some(vec![CssSource::Ttf, CssSource::Local, CssSource::Woff2]),
+            ..Default::default()
+        };
+
+        let rule = font_face_rule(
+            &FontStyles::BoldItalic,
+            700,
+            "./",
+            "dm-sans",
+            "Dm Sans",
+            &options,
+        );
+        assert!(rule.contains(
+            "\tsrc: url(\"./dm-sans-bold-italic.ttf\") format(\"truetype\"), local(\"Dm Sans Bold Italic\"), local(\"DmSans-BoldItalic\"), url(\"./dm-sans-bold-italic.woff2\") format(\"woff2\");\n"
+        ));
+    }
+
+    #[test]
+    fn family_stack_rule_declares_the_font_stack_in_a_variable() {
+        let options = CssOptions {
+            fallback: "system-ui, sans-serif".to_string(),
+            family_stack: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            family_stack_rule("dm-sans", "Dm Sans", &options),
+            ":root {\n\t--font-dm-sans: \"Dm Sans\", system-ui, sans-serif;\n}\n\n"
+        );
+        assert_eq!(
+            family_stack_rule("dm-sans", "Dm Sans", &CssOptions::default()),
+            ""
+        );
+    }
 }
@@ -0,0 +1,892 @@
+//! Runs gfontapi against a mock of the google fonts API, so the whole pipeline is tested without network access
+
+use std::{fs, path::Path, process::Output};
+
+use tempfile::TempDir;
+use tokio::process::Command;
+use wiremock::{
+    matchers::{method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Stands in for `woff2_compress` by copying the ttf file to the woff2 file it would write
+const FAKE_WOFF2_COMPRESS: &str = "#!/bin/sh\ncp \"$1\" \"${1%.ttf}.woff2\"\n";
+
+/// Starts a mock server serving the webfonts API from a fixture, with the font file urls pointing at the mock server
+async fn mock_api(fixture: &str) -> MockServer {
+    let server = MockServer::start().await;
+    let body = fs::read_to_string(Path::new("tests/fixtures").join(fixture))
+        .unwrap()
+        .replace("{server}", &server.uri());
+    Mock::given(method("GET"))
+        .and(path("/webfonts/v1/webfonts"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Runs gfontapi in a fresh home directory, so no cache or alias of the user is picked up.
+/// Prompts fail unless the args answer them with `--yes`
+async fn run_gfontapi(server: &MockServer, home: &Path, args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gfontapi"));
+    command
+        .args(args)
+        .arg("--api-base-url")
+        .arg(format!("{}/webfonts/v1/webfonts", server.uri()))
+        .arg("--api-key")
+        .arg("test-key");
+    if !args.contains(&"--yes") {
+        command.arg("--no-interactive");
+    }
+    command
+        .env("HOME", home)
+        .env_remove("XDG_CACHE_HOME")
+        .current_dir(home)
+        .output()
+        .await
+        .unwrap()
+}
+
+/// Serves the font files of the `webfonts_mock.json` fixture, each file contains its own name
+async fn mock_font_files(server: &MockServer) {
+    for font_file in ["inter-regular.ttf", "inter-700.ttf"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/s/inter/v18/{}", font_file)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(font_file.as_bytes()))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+}
+
+/// Creates a home directory with the fake `woff2_compress` in `~/.gfontapi/bin`
+#[cfg(unix)]
+fn home_with_woff2_compress() -> TempDir {
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = TempDir::new().unwrap();
+    let bin_dir = home.path().join(".gfontapi/bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+    fs::write(bin_dir.join("woff2_compress"), FAKE_WOFF2_COMPRESS).unwrap();
+    fs::set_permissions(
+        bin_dir.join("woff2_compress"),
+        fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+    home
+}
+
+/// Sorted names of the files in a directory
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut file_names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    file_names.sort();
+    file_names
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn installs_a_font_family_from_the_api() {
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let target_dir = home.path().join("fonts");
+    let font_dir = target_dir.join("inter");
+    assert_eq!(
+        file_names(&font_dir),
+        ["fonts.css", "inter-bold.woff2", "inter-regular.woff2"]
+    );
+    assert_eq!(
+        fs::read(font_dir.join("inter-bold.woff2")).unwrap(),
+        b"inter-700.ttf"
+    );
+
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    assert!(css.contains("font-family: \"Inter\";"), "{}", css);
+    assert!(
+        css.contains("src: url(\"./fonts/inter/inter-regular.woff2\")"),
+        "{}",
+        css
+    );
+    assert!(css.contains("font-weight: 700;"), "{}", css);
+
+    let lockfile = fs::read_to_string(target_dir.join("gfontapi.lock")).unwrap();
+    assert!(lockfile.contains("\"version\": \"v18\""), "{}", lockfile);
+    assert!(!lockfile.contains("test-key"), "{}", lockfile);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn names_font_files_after_the_style_only() {
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--filename-style", "style-only"],
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/inter");
+    assert_eq!(
+        file_names(&font_dir),
+        ["bold.woff2", "fonts.css", "regular.woff2"]
+    );
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    assert!(
+        css.contains("src: url(\"./fonts/inter/bold.woff2\")"),
+        "{}",
+        css
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn copies_font_files_from_the_store_instead_of_downloading_them_again() {
+    let server = mock_api("webfonts_mock.json").await;
+    // Each font file is expected to be downloaded once across both projects
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+
+    for project in ["first", "second"] {
+        let output = run_gfontapi(
+            &server,
+            home.path(),
+            &["Inter", "--store", "--target-dir", project],
+        )
+        .await;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    assert_eq!(
+        fs::read_dir(home.path().join(".cache/gfontapi/blobs"))
+            .unwrap()
+            .count(),
+        2
+    );
+    assert_eq!(
+        fs::read(home.path().join("second/inter/inter-regular.woff2")).unwrap(),
+        fs::read(home.path().join("first/inter/inter-regular.woff2")).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn lays_out_a_directory_per_weight() {
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--layout", "dir-per-weight"],
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/inter");
+    assert_eq!(file_names(&font_dir), ["400", "700", "fonts.css"]);
+    assert_eq!(file_names(&font_dir.join("700")), ["inter-bold.woff2"]);
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    assert!(
+        css.contains("src: url(\"./fonts/inter/700/inter-bold.woff2\")"),
+        "{}",
+        css
+    );
+
+    let lockfile = fs::read_to_string(home.path().join("fonts/gfontapi.lock")).unwrap();
+    assert!(
+        lockfile.contains("\"400/inter-regular.woff2\""),
+        "{}",
+        lockfile
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn writes_a_json_manifest_matching_the_css() {
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--emit-json-and-css"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/inter");
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(font_dir.join("fonts.json")).unwrap()).unwrap();
+    assert_eq!(manifest["family"], "Inter");
+    assert_eq!(manifest["stylesheet"], "fonts.css");
+
+    let faces = manifest["faces"].as_array().unwrap();
+    assert_eq!(faces.len(), css.matches("@font-face").count());
+    assert_eq!(faces[1]["weight"], 700);
+    let bold_url = faces[1]["files"]["woff2"].as_str().unwrap();
+    assert_eq!(bold_url, "./fonts/inter/inter-bold.woff2");
+    assert!(css.contains(bold_url), "{}", css);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn refetches_a_font_family_when_the_response_is_cut_off() {
+    let server = mock_api("webfonts_mock.json").await;
+    Mock::given(method("GET"))
+        .and(path("/webfonts/v1/webfonts"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"kind\": \"webfonts#webf"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--refetch-on-parse-error", "--verbose"],
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Could not parse the response for `Inter`, fetching it again"),
+        "{}",
+        stdout
+    );
+    assert!(home.path().join("fonts/inter/inter-regular.woff2").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn prunes_font_files_that_are_not_selected_once_every_file_installed() {
+    let home = home_with_woff2_compress();
+    let font_dir = home.path().join("fonts/inter");
+    fs::create_dir_all(&font_dir).unwrap();
+    for font_file in ["inter-bold.woff2", "inter-light.woff2", "inter-light.ttf"] {
+        fs::write(font_dir.join(font_file), font_file).unwrap();
+    }
+
+    // A selected style that fails to download keeps its previous file, and nothing is pruned
+    let server = mock_api("webfonts_mock.json").await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-regular.ttf"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"inter-regular.ttf".as_slice()))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-700.ttf"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--prune", "--yes", "--retries", "0"],
+    )
+    .await;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Not pruning the font files"), "{}", stderr);
+    assert_eq!(
+        file_names(&font_dir),
+        [
+            "fonts.css",
+            "inter-bold.woff2",
+            "inter-light.ttf",
+            "inter-light.woff2",
+            "inter-regular.woff2"
+        ]
+    );
+    assert_eq!(
+        fs::read(font_dir.join("inter-bold.woff2")).unwrap(),
+        b"inter-bold.woff2"
+    );
+
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--prune", "--yes"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        file_names(&font_dir),
+        ["fonts.css", "inter-bold.woff2", "inter-regular.woff2"]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn installs_the_variable_font_files_with_only_variable() {
+    let server = mock_api("webfonts_variable.json").await;
+    for (font_file, expected_requests) in [
+        ("inter-variable.ttf", 1),
+        ("inter-italic-variable.ttf", 1),
+        ("inter-regular.ttf", 0),
+        ("inter-italic.ttf", 0),
+        ("inter-700.ttf", 0),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(format!("/s/inter/v18/{}", font_file)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(font_file.as_bytes()))
+            .expect(expected_requests)
+            .mount(&server)
+            .await;
+    }
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--only-variable"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/inter");
+    assert_eq!(
+        fs::read(font_dir.join("inter-regular.woff2")).unwrap(),
+        b"inter-variable.ttf"
+    );
+    assert_eq!(
+        fs::read(font_dir.join("inter-regular-italic.woff2")).unwrap(),
+        b"inter-italic-variable.ttf"
+    );
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    assert!(css.contains("font-weight: 100 900;"), "{}", css);
+}
+
+#[tokio::test]
+async fn fails_with_only_variable_for_a_family_without_variable_font_files() {
+    let server = mock_api("webfonts_mock.json").await;
+    let home = TempDir::new().unwrap();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--only-variable"]).await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Inter has no variable font"), "{}", stderr);
+    assert!(!home.path().join("fonts/inter").exists());
+}
+
+#[tokio::test]
+async fn lists_the_catalog_from_the_api() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/webfonts/v1/webfonts"))
+        .and(query_param("key", "test-key"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(fs::read_to_string("tests/fixtures/catalog_mock.json").unwrap()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let home = TempDir::new().unwrap();
+    let output = run_gfontapi(&server, home.path(), &["list", "--category", "sans-serif"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("DM Sans"), "{}", stdout);
+    assert!(stdout.contains("Inter"), "{}", stdout);
+    assert!(!stdout.contains("Lora"), "{}", stdout);
+}
+
+#[tokio::test]
+async fn rejects_a_family_index_past_the_matches() {
+    let server = mock_api("webfonts_mock.json").await;
+    let home = TempDir::new().unwrap();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--family-index", "1"]).await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("matched 1 font families, --family-index 1 is out of range (0 to 0)"),
+        "{}",
+        stderr
+    );
+    assert!(!home.path().join("fonts/inter").exists());
+}
+
+#[tokio::test]
+async fn aborts_on_a_missing_weight_before_downloading() {
+    let server = mock_api("webfonts_mock.json").await;
+    let home = TempDir::new().unwrap();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--weights", "400,900", "--abort-on-missing-weight"],
+    )
+    .await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Inter has no weight 900, the available weights are 400, 700"),
+        "{}",
+        stderr
+    );
+    assert!(!home.path().join("fonts/inter").exists());
+}
+
+#[tokio::test]
+async fn searches_a_catalog_file_without_the_api() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let home = TempDir::new().unwrap();
+    let catalog_file =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/catalog_mock.json");
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &[
+            "search",
+            "sans",
+            "--catalog-file",
+            catalog_file.to_str().unwrap(),
+        ],
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("DM Sans"), "{}", stdout);
+    assert!(!stdout.contains("Lora"), "{}", stdout);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn reports_conversion_failures_apart_from_download_failures() {
+    let server = mock_api("webfonts_mock.json").await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-regular.ttf"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"inter-regular.ttf".as_slice()))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-700.ttf"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let home = home_with_woff2_compress();
+    fs::write(
+        home.path().join(".gfontapi/bin/woff2_compress"),
+        "#!/bin/sh\nexit 1\n",
+    )
+    .unwrap();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--retries", "0"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stdout.contains("==regular (conversion failed)"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("==bold (download failed)"), "{}", stdout);
+    assert!(
+        stderr.contains("1 font files could not be downloaded"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("1 font files were downloaded but could not be converted"),
+        "{}",
+        stderr
+    );
+}
+
+#[tokio::test]
+async fn probe_reports_a_rejected_api_key() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/webfonts/v1/webfonts"))
+        .and(query_param("family", "Roboto"))
+        .respond_with(ResponseTemplate::new(400).set_body_string(
+            r#"{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT"}}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let home = TempDir::new().unwrap();
+    let output = run_gfontapi(&server, home.path(), &["probe"]).await;
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("connected to the api in"), "{}", stdout);
+    assert!(stdout.contains("api key:"), "{}", stdout);
+    assert!(stdout.contains("API key not valid"), "{}", stdout);
+}
+
+#[tokio::test]
+async fn prints_install_failures_as_json_with_json_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/webfonts/v1/webfonts"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let home = TempDir::new().unwrap();
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--json-errors", "--retries", "0"],
+    )
+    .await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(error["code"], "install_failed");
+    assert_eq!(error["context"]["family"], "Inter");
+    assert!(
+        error["message"].as_str().unwrap().contains("500"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn prints_font_file_and_catalog_errors_as_json_with_json_errors() {
+    let server = mock_api("webfonts_mock.json").await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-regular.ttf"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"inter-regular.ttf".as_slice()))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-700.ttf"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let home = home_with_woff2_compress();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--json-errors", "--retries", "0"],
+    )
+    .await;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Caused by"), "{}", stderr);
+    let errors: Vec<serde_json::Value> = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let error = errors
+        .iter()
+        .find(|error| error["code"] == "download_failed")
+        .unwrap_or_else(|| panic!("{}", stderr));
+    assert_eq!(error["context"]["family"], "inter");
+    assert_eq!(error["context"]["style"], "bold");
+
+    let catalog_file =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/catalog_mock.json");
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &[
+            "search",
+            "nothing like it",
+            "--catalog-file",
+            catalog_file.to_str().unwrap(),
+            "--json-errors",
+        ],
+    )
+    .await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(error["code"], "no_match");
+    assert_eq!(error["context"]["query"], "nothing like it");
+}
+
+#[tokio::test]
+async fn lists_the_font_styles_and_asks_before_downloading_them() {
+    let server = mock_api("webfonts_mock.json").await;
+    let home = TempDir::new().unwrap();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--list-weights", "--weights", "700"],
+    )
+    .await;
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Inter has"), "{}", stdout);
+    assert!(stdout.contains("==bold"), "{}", stdout);
+    assert!(!stdout.contains("==regular"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("needs confirmation"), "{}", stderr);
+    assert!(!home.path().join("fonts/inter").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn installs_a_font_under_the_names_of_the_family_name_map() {
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+    fs::write(
+        home.path().join("names.txt"),
+        "# design tokens\nInter -> Brand Sans -> brand-sans\n",
+    )
+    .unwrap();
+
+    let output = run_gfontapi(
+        &server,
+        home.path(),
+        &["Inter", "--family-name-map", "names.txt"],
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/brand-sans");
+    assert_eq!(
+        file_names(&font_dir),
+        [
+            "brand-sans-bold.woff2",
+            "brand-sans-regular.woff2",
+            "fonts.css"
+        ]
+    );
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    assert!(css.contains("font-family: \"Brand Sans\";"), "{}", css);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn refuses_font_files_that_do_not_match_the_lockfile() {
+    let home = home_with_woff2_compress();
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let output = run_gfontapi(&server, home.path(), &["Inter"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let font_file = home.path().join("fonts/inter/inter-regular.woff2");
+    let locked_font = fs::read(&font_file).unwrap();
+
+    // The same urls now serve different files
+    let changed_server = mock_api("webfonts_mock.json").await;
+    for font_file in ["inter-regular.ttf", "inter-700.ttf"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/s/inter/v18/{}", font_file)))
+            .respond_with(ResponseTemplate::new(200).set_body_string("tampered"))
+            .mount(&changed_server)
+            .await;
+    }
+
+    let output = run_gfontapi(&changed_server, home.path(), &["Inter"]).await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("does not match gfontapi.lock"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("--update-lock"), "{}", stderr);
+    assert_eq!(fs::read(&font_file).unwrap(), locked_font);
+    assert_eq!(
+        file_names(&home.path().join("fonts/inter")),
+        ["fonts.css", "inter-bold.woff2", "inter-regular.woff2"]
+    );
+
+    let output = run_gfontapi(&changed_server, home.path(), &["Inter", "--update-lock"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_ne!(fs::read(&font_file).unwrap(), locked_font);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn leaves_the_font_directory_untouched_when_a_file_does_not_match_the_lockfile() {
+    let home = home_with_woff2_compress();
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let output = run_gfontapi(&server, home.path(), &["Inter"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let font_dir = home.path().join("fonts/inter");
+    let read_files = || {
+        file_names(&font_dir)
+            .into_iter()
+            .map(|file_name| {
+                let contents = fs::read(font_dir.join(&file_name)).unwrap();
+                (file_name, contents)
+            })
+            .collect::<Vec<_>>()
+    };
+    let installed_files = read_files();
+    let lockfile = fs::read(home.path().join("fonts/gfontapi.lock")).unwrap();
+
+    // Only the bold file changed, the regular file still matches and would be converted to ttf too
+    let changed_server = mock_api("webfonts_mock.json").await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-regular.ttf"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"inter-regular.ttf".as_slice()))
+        .mount(&changed_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/inter/v18/inter-700.ttf"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("tampered"))
+        .mount(&changed_server)
+        .await;
+
+    let output = run_gfontapi(&changed_server, home.path(), &["Inter", "--format", "both"]).await;
+    assert!(!output.status.success());
+    assert_eq!(read_files(), installed_files);
+    assert_eq!(
+        fs::read(home.path().join("fonts/gfontapi.lock")).unwrap(),
+        lockfile
+    );
+}
+
+#[tokio::test]
+async fn keeps_the_otf_files_of_a_family_distributed_as_opentype() {
+    let server = mock_api("webfonts_otf.json").await;
+    for font_file in ["inter-regular.otf", "inter-700.otf"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/s/inter/v18/{}", font_file)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(font_file.as_bytes()))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    let home = TempDir::new().unwrap();
+
+    let output = run_gfontapi(&server, home.path(), &["Inter", "--format", "otf"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/inter");
+    assert_eq!(
+        file_names(&font_dir),
+        ["fonts.css", "inter-bold.otf", "inter-regular.otf"]
+    );
+    let css = fs::read_to_string(font_dir.join("fonts.css")).unwrap();
+    assert!(
+        css.contains("src: url(\"./fonts/inter/inter-bold.otf\") format(\"opentype\");"),
+        "{}",
+        css
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn imports_an_exported_bundle_into_another_home() {
+    let server = mock_api("webfonts_mock.json").await;
+    mock_font_files(&server).await;
+    let home = home_with_woff2_compress();
+    let output = run_gfontapi(&server, home.path(), &["Inter"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = run_gfontapi(&server, home.path(), &["export", "inter.gfbundle"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let other_home = TempDir::new().unwrap();
+    let bundle = home.path().join("inter.gfbundle");
+    let output = run_gfontapi(
+        &server,
+        other_home.path(),
+        &["import", bundle.to_str().unwrap()],
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let font_dir = home.path().join("fonts/inter");
+    let imported_dir = other_home.path().join("fonts/inter");
+    assert_eq!(file_names(&imported_dir), file_names(&font_dir));
+    for file_name in file_names(&font_dir) {
+        assert_eq!(
+            fs::read(imported_dir.join(&file_name)).unwrap(),
+            fs::read(font_dir.join(&file_name)).unwrap(),
+            "{}",
+            file_name
+        );
+    }
+    assert_eq!(
+        file_names(&other_home.path().join("fonts")),
+        ["gfontapi.lock", "inter"]
+    );
+    let lockfile = fs::read_to_string(other_home.path().join("fonts/gfontapi.lock")).unwrap();
+    assert!(lockfile.contains("inter-regular.woff2"), "{}", lockfile);
+}